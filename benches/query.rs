@@ -0,0 +1,194 @@
+#![allow(dead_code)]
+
+#[path = "../src/archetype.rs"]
+mod archetype;
+#[path = "../src/cell.rs"]
+mod cell;
+#[path = "../src/components.rs"]
+mod components;
+#[path = "../src/entities.rs"]
+mod entities;
+#[path = "../src/geom.rs"]
+mod geom;
+#[path = "../src/hash.rs"]
+mod hash;
+#[path = "../src/query.rs"]
+mod query;
+
+use archetype::*;
+use components::*;
+use criterion::*;
+use entities::*;
+use geom::*;
+use query::*;
+
+const ENTITIES: usize = 5000;
+const RARE_EVERY: usize = 100;
+
+fn setup() -> (CContainer<Velocity>, CContainer<Team>) {
+    let mut allocator = EntityAllocator::default();
+    let mut velocities = CContainer::<Velocity>::default();
+    let mut teams = CContainer::<Team>::default();
+    for i in 0..ENTITIES {
+        let entity_id = allocator.allocate();
+        velocities.push(entity_id, Velocity(Vector::new(1f32, 1f32)));
+        if i % RARE_EVERY == 0 {
+            teams.push(entity_id, Team::new(1));
+        }
+    }
+    (velocities, teams)
+}
+
+fn join_rare(c: &mut Criterion) {
+    let (mut velocities, teams) = setup();
+    let mut group = c.benchmark_group("velocity x rare team");
+
+    // What the ZipEntity iterators did: walk the mutable container and look
+    // every entity up in the other one.
+    group.bench_function("first container drives", |b| {
+        b.iter(|| {
+            velocities.iter_mut().for_each(|(entity_id, velocity)| {
+                if let Some(team) = teams.get(entity_id) {
+                    velocity.x += team.team_id() as f32;
+                }
+            });
+        })
+    });
+    group.bench_function("smallest container drives", |b| {
+        b.iter(|| {
+            (&mut velocities, &teams)
+                .query()
+                .for_each(|(_, velocity, team)| {
+                    velocity.x += team.team_id() as f32;
+                });
+        })
+    });
+    group.finish();
+}
+
+fn lookup(c: &mut Criterion) {
+    let mut allocator = EntityAllocator::default();
+    let mut hashed = CContainer::<Position>::default();
+    let mut sparse = CContainer::<Position>::sparse();
+    let entities: Vec<_> = (0..ENTITIES).map(|_| allocator.allocate()).collect();
+    for entity_id in &entities {
+        hashed.push(*entity_id, Position::default());
+        sparse.push(*entity_id, Position::default());
+    }
+    let mut group = c.benchmark_group("lookup every entity");
+    group.bench_function("hash index", |b| {
+        b.iter(|| {
+            entities
+                .iter()
+                .filter(|e| hashed.get(**e).is_some())
+                .count()
+        })
+    });
+    group.bench_function("sparse index", |b| {
+        b.iter(|| {
+            entities
+                .iter()
+                .filter(|e| sparse.get(**e).is_some())
+                .count()
+        })
+    });
+    group.finish();
+}
+
+// Columns filled in different orders, as when components are added to
+// entities one system at a time.
+fn group_columns(
+    grouped: bool,
+) -> (
+    CContainer<Position>,
+    CContainer<Velocity>,
+    CContainer<Direction>,
+) {
+    let mut allocator = EntityAllocator::default();
+    let mut positions = CContainer::<Position>::sparse();
+    let mut velocities = CContainer::<Velocity>::sparse();
+    let mut directions = CContainer::<Direction>::sparse();
+    let entities: Vec<_> = (0..ENTITIES).map(|_| allocator.allocate()).collect();
+    for entity_id in &entities {
+        positions.push(*entity_id, Position::default());
+    }
+    for entity_id in entities.iter().rev() {
+        velocities.push(*entity_id, Velocity(Vector::new(1f32, 1f32)));
+    }
+    for entity_id in entities
+        .iter()
+        .step_by(2)
+        .chain(entities.iter().skip(1).step_by(2))
+    {
+        directions.push(*entity_id, Direction::default());
+    }
+    if grouped {
+        let mut archetype = Archetype::new::<(Position, Velocity, Direction)>(0);
+        let mut columns: [&mut dyn TableColumn; 3] =
+            [&mut positions, &mut velocities, &mut directions];
+        for column in columns.iter_mut() {
+            column.set_table(Some(archetype.id()));
+        }
+        archetype.rebuild(&mut columns);
+    }
+    (positions, velocities, directions)
+}
+
+fn scan_group(c: &mut Criterion) {
+    let mut group = c.benchmark_group("position x velocity x direction");
+    for &(name, grouped) in &[("component containers", false), ("archetype columns", true)] {
+        let (mut positions, velocities, directions) = group_columns(grouped);
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                (&mut positions, &velocities, &directions).query().for_each(
+                    |(_, position, velocity, _)| {
+                        position.x += velocity.x;
+                    },
+                );
+            })
+        });
+    }
+    group.finish();
+}
+
+// Per-entity work heavy enough for splitting it across threads to pay off.
+#[cfg(all(feature = "parallel", not(target_arch = "wasm32")))]
+fn par_heavy(c: &mut Criterion) {
+    use rayon::prelude::*;
+    let (mut velocities, _) = setup();
+    let heavy = |velocity: &mut Velocity| {
+        for _ in 0..100 {
+            velocity.x = (velocity.x * 1.0001).sin() + 1f32;
+        }
+    };
+    let mut group = c.benchmark_group("heavy velocity update");
+    group.bench_function("iter_mut", |b| {
+        b.iter(|| {
+            velocities
+                .iter_mut()
+                .for_each(|(_, velocity)| heavy(velocity))
+        })
+    });
+    group.bench_function("par_iter_mut", |b| {
+        b.iter(|| {
+            velocities
+                .par_iter_mut()
+                .for_each(|(_, velocity)| heavy(velocity))
+        })
+    });
+    group.bench_function("query par_iter", |b| {
+        b.iter(|| {
+            (&mut velocities,)
+                .query()
+                .par_iter()
+                .for_each(|(_, velocity)| heavy(velocity))
+        })
+    });
+    group.finish();
+}
+
+#[cfg(all(feature = "parallel", not(target_arch = "wasm32")))]
+criterion_group!(benches, join_rare, lookup, scan_group, par_heavy);
+#[cfg(not(all(feature = "parallel", not(target_arch = "wasm32"))))]
+criterion_group!(benches, join_rare, lookup, scan_group);
+criterion_main!(benches);
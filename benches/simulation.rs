@@ -0,0 +1,37 @@
+use criterion::*;
+use rust_ecs_game::Simulation;
+
+// A few hundred characters, as in the rollback target: one hero in the
+// middle and rows of enemies chasing it.
+const ENEMIES: usize = 300;
+
+fn setup() -> Simulation {
+    let mut simulation = Simulation::new().unwrap();
+    for i in 0..ENEMIES {
+        let x = (i % 20) as f32 * 40f32;
+        let y = (i / 20) as f32 * 40f32;
+        simulation.spawn("enemy", x, y).unwrap();
+    }
+    // Let the enemies start moving and hitting so every system has work.
+    for _ in 0..60 {
+        simulation.step();
+    }
+    simulation
+}
+
+fn step(c: &mut Criterion) {
+    let mut simulation = setup();
+    c.bench_function("step", |b| b.iter(|| simulation.step()));
+}
+
+fn rollback(c: &mut Criterion) {
+    let mut simulation = setup();
+    let mut group = c.benchmark_group("rollback");
+    group.bench_function("snapshot", |b| b.iter(|| simulation.snapshot()));
+    let snapshot = simulation.snapshot();
+    group.bench_function("restore", |b| b.iter(|| simulation.restore(&snapshot)));
+    group.finish();
+}
+
+criterion_group!(benches, step, rollback);
+criterion_main!(benches);
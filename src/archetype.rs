@@ -0,0 +1,127 @@
+use crate::components::*;
+use crate::entities::*;
+use std::any::TypeId;
+
+// What an archetype needs from each of its containers to keep their rows in
+// line.
+pub(crate) trait TableColumn {
+    fn len(&self) -> usize;
+    fn row_of(&self, entity_id: EntityID) -> Option<usize>;
+    fn entity_at(&self, row: usize) -> EntityID;
+    fn swap_rows(&mut self, a: usize, b: usize);
+    fn set_table(&mut self, table: Option<u32>);
+}
+
+impl<T> TableColumn for CContainer<T> {
+    fn len(&self) -> usize {
+        CContainer::len(self)
+    }
+    fn row_of(&self, entity_id: EntityID) -> Option<usize> {
+        self.index_of(entity_id)
+    }
+    fn entity_at(&self, row: usize) -> EntityID {
+        CContainer::entity_at(self, row)
+    }
+    fn swap_rows(&mut self, a: usize, b: usize) {
+        CContainer::swap_rows(self, a, b);
+    }
+    fn set_table(&mut self, table: Option<u32>) {
+        CContainer::set_table(self, table);
+    }
+}
+
+// Table storage for component types that usually come together, such as the
+// position, velocity, direction and view of every character. Each type keeps
+// its own container as a column of the table, and an entity that has all of
+// them sits in the same row of every column, among the first `len` rows.
+// Joining the columns then reads them front to back in step instead of
+// looking each entity up. Entities that have only some of the types are kept
+// after those rows.
+//
+// The rows are kept in line by the world as components are inserted and
+// removed, so the containers of an archetype must not be pushed to or removed
+// from directly.
+pub(crate) struct Archetype {
+    id: u32,
+    types: Vec<TypeId>,
+    len: usize,
+}
+
+impl Archetype {
+    pub fn new<R: ArchetypeRow>(id: u32) -> Self {
+        Self {
+            id: id,
+            types: R::types(),
+            len: 0,
+        }
+    }
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+    // Columns are passed in this order.
+    pub fn types(&self) -> &[TypeId] {
+        &self.types
+    }
+    // Moves `entity_id` into the shared rows once it has every type. Called
+    // after a component of the archetype is inserted.
+    pub fn join(&mut self, columns: &mut [&mut dyn TableColumn], entity_id: EntityID) {
+        let mut rows = Vec::with_capacity(columns.len());
+        for column in columns.iter() {
+            match column.row_of(entity_id) {
+                Some(row) => rows.push(row),
+                None => return,
+            }
+        }
+        if rows[0] < self.len {
+            return;
+        }
+        for (column, row) in columns.iter_mut().zip(rows) {
+            column.swap_rows(row, self.len);
+        }
+        self.len += 1;
+    }
+    // Moves `entity_id` out of the shared rows. Called before a component of
+    // the archetype is removed.
+    pub fn leave(&mut self, columns: &mut [&mut dyn TableColumn], entity_id: EntityID) {
+        let row = match columns[0].row_of(entity_id) {
+            Some(row) if row < self.len => row,
+            _ => return,
+        };
+        self.len -= 1;
+        for column in columns.iter_mut() {
+            column.swap_rows(row, self.len);
+        }
+    }
+    // Lines the rows up again after the columns were replaced wholesale, as
+    // on load. Columns that are already in line keep their order.
+    pub fn rebuild(&mut self, columns: &mut [&mut dyn TableColumn]) {
+        self.len = 0;
+        for row in 0..columns[0].len() {
+            let entity_id = columns[0].entity_at(row);
+            self.join(columns, entity_id);
+        }
+    }
+}
+
+// A tuple of the component types making up an archetype.
+pub(crate) trait ArchetypeRow: 'static {
+    fn types() -> Vec<TypeId>;
+}
+
+macro_rules! impl_archetype_row {
+    ($($component:ident),+) => {
+        impl<$($component: 'static),+> ArchetypeRow for ($($component,)+) {
+            fn types() -> Vec<TypeId> {
+                vec![$(TypeId::of::<$component>()),+]
+            }
+        }
+    };
+}
+
+impl_archetype_row!(A, B);
+impl_archetype_row!(A, B, C);
+impl_archetype_row!(A, B, C, D);
+impl_archetype_row!(A, B, C, D, E);
+impl_archetype_row!(A, B, C, D, E, F);
+impl_archetype_row!(A, B, C, D, E, F, G);
+impl_archetype_row!(A, B, C, D, E, F, G, H);
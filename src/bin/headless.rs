@@ -0,0 +1,98 @@
+// Runs the game without a window and prints the final state as JSON.
+//
+//     headless [--assets DIR] [--save-scene SCENE] [TICKS] [SCRIPT] [RECORDING]
+//     headless [--assets DIR] [--save-scene SCENE] --replay RECORDING
+//
+// SCRIPT is a JSON file of `[tick, input]` pairs sorted by tick, where each
+// input is held from its tick until the next one, e.g.
+// `[[0, {"right": true}], [60, {"attack": true}], [61, {}]]`. If RECORDING is
+// given the run is recorded to it. With `--replay` a recording is played back
+// instead, and the run fails unless it ends in the recorded state. With
+// `--save-scene` the prefab instances left at the end are written to SCENE.
+// The level is read from DIR, or from the crate's `static` directory.
+//
+// Log messages go to stderr, so with `LOG_STATE_HASH` set the state hash of
+// every step can be diffed between two runs.
+
+use rust_ecs_game::{Input, Recording, Simulation};
+use std::error::Error;
+
+struct StderrLogger;
+
+impl log::Log for StderrLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() <= log::Level::Info
+    }
+    fn log(&self, record: &log::Record) {
+        if self.enabled(record.metadata()) {
+            eprintln!("{}: {}", record.level(), record.args());
+        }
+    }
+    fn flush(&self) {}
+}
+
+// Removes `name` and the value after it from `args`, wherever they are.
+fn take_option(args: &mut Vec<String>, name: &str) -> Result<Option<String>, Box<dyn Error>> {
+    let index = match args.iter().position(|arg| arg == name) {
+        Some(index) => index,
+        None => return Ok(None),
+    };
+    if index + 1 >= args.len() {
+        return Err(format!("{} needs a value", name).into());
+    }
+    Ok(args.drain(index..index + 2).nth(1))
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    log::set_logger(&StderrLogger).expect("a logger was already set");
+    log::set_max_level(log::LevelFilter::Info);
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    let scene_path = take_option(&mut args, "--save-scene")?;
+    let mut simulation = match take_option(&mut args, "--assets")? {
+        Some(assets) => Simulation::with_assets(assets)?,
+        None => Simulation::new()?,
+    };
+
+    if args.first().map(String::as_str) == Some("--replay") {
+        let path = args.get(1).ok_or("--replay needs a recording")?;
+        if let Err(err) = simulation.replay(Recording::load(path)?) {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+        eprintln!("replay finished in the recorded state");
+    } else {
+        let ticks = match args.first() {
+            Some(ticks) => ticks.parse()?,
+            None => 60,
+        };
+        let script: Vec<(u32, Input)> = match args.get(1) {
+            Some(path) => serde_json::from_str(&std::fs::read_to_string(path)?)?,
+            None => Vec::new(),
+        };
+        let recording_path = args.get(2);
+
+        if recording_path.is_some() {
+            simulation.start_recording()?;
+        }
+        let mut input = Input::default();
+        let mut next = 0;
+        simulation.run(ticks, |tick| {
+            while next < script.len() && script[next].0 <= tick {
+                input = script[next].1;
+                next += 1;
+            }
+            input
+        });
+        if let Some(path) = recording_path {
+            if let Some(recording) = simulation.stop_recording() {
+                recording.save(path)?;
+            }
+        }
+    }
+
+    if let Some(path) = scene_path {
+        simulation.save_scene(path)?;
+    }
+    println!("{}", serde_json::to_string_pretty(&simulation.state()?)?);
+    Ok(())
+}
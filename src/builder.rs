@@ -0,0 +1,143 @@
+use crate::components::*;
+use crate::entities::*;
+use crate::world::*;
+use serde::Deserialize;
+
+// A group of components inserted onto one entity together.
+pub(crate) trait Bundle: Send + Sync + 'static {
+    fn insert_into(self, world: &mut World, entity_id: EntityID);
+}
+
+macro_rules! impl_bundle {
+    ($($index:tt $component:ident),+) => {
+        impl<$($component: Send + Sync + 'static),+> Bundle for ($($component,)+) {
+            fn insert_into(self, world: &mut World, entity_id: EntityID) {
+                $(world.insert(entity_id, self.$index);)+
+            }
+        }
+    };
+}
+
+impl_bundle!(0 A);
+impl_bundle!(0 A, 1 B);
+impl_bundle!(0 A, 1 B, 2 C);
+impl_bundle!(0 A, 1 B, 2 C, 3 D);
+impl_bundle!(0 A, 1 B, 2 C, 3 D, 4 E);
+impl_bundle!(0 A, 1 B, 2 C, 3 D, 4 E, 5 F);
+impl_bundle!(0 A, 1 B, 2 C, 3 D, 4 E, 5 F, 6 G);
+impl_bundle!(0 A, 1 B, 2 C, 3 D, 4 E, 5 F, 6 G, 7 H);
+
+// Returned by `World::spawn`. The entity exists as soon as the builder does;
+// `with` adds components to it one at a time.
+pub(crate) struct EntityBuilder<'w> {
+    world: &'w mut World,
+    entity_id: EntityID,
+}
+
+impl<'w> EntityBuilder<'w> {
+    pub fn new(world: &'w mut World) -> Self {
+        let entity_id = world.create_entity();
+        Self::from_id(world, entity_id)
+    }
+    pub fn from_id(world: &'w mut World, entity_id: EntityID) -> Self {
+        Self {
+            world: world,
+            entity_id: entity_id,
+        }
+    }
+    pub fn with<T: Send + Sync + 'static>(self, value: T) -> Self {
+        self.world.insert(self.entity_id, value);
+        self
+    }
+    pub fn with_bundle<B: Bundle>(self, bundle: B) -> Self {
+        bundle.insert_into(self.world, self.entity_id);
+        self
+    }
+    pub fn id(self) -> EntityID {
+        self.entity_id
+    }
+}
+
+// Everything a character needs to move, take hits and be drawn. Prefabs use
+// it as `Character`; the animator is loaded on its own, by name.
+#[derive(Deserialize)]
+pub(crate) struct CharacterBundle {
+    pub team: Team,
+    #[serde(default)]
+    pub position: Position,
+    pub view: CharacterView,
+}
+
+impl Bundle for CharacterBundle {
+    fn insert_into(self, world: &mut World, entity_id: EntityID) {
+        (
+            self.team,
+            self.position,
+            Direction::default(),
+            Velocity::default(),
+            BodyDefenseCollider::default(),
+            self.view,
+        )
+            .insert_into(world, entity_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geom::*;
+    use crate::prefab::*;
+
+    #[test]
+    fn bundles_insert_every_component() {
+        let mut world = World::default();
+        let entity_id = world
+            .spawn()
+            .with(Input::default())
+            .with_bundle(CharacterBundle {
+                team: Team::new(1),
+                position: Position(Vector::new(20f32, 30f32)),
+                view: CharacterView {
+                    radius: 15f32,
+                    ..Default::default()
+                },
+            })
+            .with_bundle((MoveTarget::default(), BodyWeaponCollider::default()))
+            .id();
+
+        assert!(world.is_alive(entity_id));
+        assert!(world.get::<Input>(entity_id).is_some());
+        assert_eq!(world.get::<Team>(entity_id).unwrap().team_id(), 1);
+        assert_eq!(world.get::<Position>(entity_id).unwrap().x, 20f32);
+        assert_eq!(world.get::<CharacterView>(entity_id).unwrap().radius, 15f32);
+        assert!(world.get::<Direction>(entity_id).is_some());
+        assert!(world.get::<Velocity>(entity_id).is_some());
+        assert!(world.get::<BodyDefenseCollider>(entity_id).is_some());
+        assert!(world.get::<MoveTarget>(entity_id).is_some());
+        assert!(world.get::<BodyWeaponCollider>(entity_id).is_some());
+    }
+
+    #[test]
+    fn prefabs_spawn_bundles() {
+        let mut prefabs = Prefabs::default();
+        prefabs.register_bundle::<CharacterBundle>("Character");
+        let json = r#"{
+            "enemy": {
+                "Character": { "team": { "team_id": 1 }, "view": { "radius": 15 } }
+            }
+        }"#;
+        prefabs.load_str(json).unwrap();
+        let mut world = World::default();
+        let entity_id = prefabs
+            .spawn(&mut world, "enemy")
+            .unwrap()
+            .with(Position(Vector::new(5f32, 0f32)))
+            .id();
+
+        assert_eq!(world.get::<PrefabName>(entity_id).unwrap().0, "enemy");
+        assert_eq!(world.get::<Team>(entity_id).unwrap().team_id(), 1);
+        assert_eq!(world.get::<CharacterView>(entity_id).unwrap().radius, 15f32);
+        assert_eq!(world.get::<Position>(entity_id).unwrap().x, 5f32);
+        assert!(world.get::<Velocity>(entity_id).is_some());
+    }
+}
@@ -0,0 +1,159 @@
+use std::cell::UnsafeCell;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+const WRITING: usize = usize::MAX;
+
+// A RefCell that can be shared between threads. Like RefCell, borrowing
+// something that is already borrowed incompatibly panics instead of waiting:
+// the schedule never runs systems with conflicting access at the same time,
+// so a conflict here is a bug rather than contention.
+pub(crate) struct SyncCell<T: ?Sized> {
+    borrows: AtomicUsize,
+    value: UnsafeCell<T>,
+}
+
+unsafe impl<T: ?Sized + Send> Send for SyncCell<T> {}
+unsafe impl<T: ?Sized + Send + Sync> Sync for SyncCell<T> {}
+
+impl<T> SyncCell<T> {
+    pub fn new(value: T) -> Self {
+        Self {
+            borrows: AtomicUsize::new(0),
+            value: UnsafeCell::new(value),
+        }
+    }
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+}
+
+impl<T: ?Sized> SyncCell<T> {
+    pub fn get_mut(&mut self) -> &mut T {
+        self.value.get_mut()
+    }
+    pub fn borrow(&self) -> CellRef<'_, T> {
+        let mut borrows = self.borrows.load(Ordering::Relaxed);
+        loop {
+            if borrows >= WRITING - 1 {
+                panic!("already mutably borrowed");
+            }
+            match self.borrows.compare_exchange_weak(
+                borrows,
+                borrows + 1,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => break,
+                Err(current) => borrows = current,
+            }
+        }
+        CellRef {
+            value: unsafe { &*self.value.get() },
+            borrow: BorrowRef {
+                borrows: &self.borrows,
+            },
+        }
+    }
+    pub fn borrow_mut(&self) -> CellRefMut<'_, T> {
+        if self
+            .borrows
+            .compare_exchange(0, WRITING, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            panic!("already borrowed");
+        }
+        CellRefMut {
+            value: unsafe { &mut *self.value.get() },
+            borrow: BorrowRefMut {
+                borrows: &self.borrows,
+            },
+        }
+    }
+}
+
+struct BorrowRef<'a> {
+    borrows: &'a AtomicUsize,
+}
+
+impl Drop for BorrowRef<'_> {
+    fn drop(&mut self) {
+        self.borrows.fetch_sub(1, Ordering::Release);
+    }
+}
+
+struct BorrowRefMut<'a> {
+    borrows: &'a AtomicUsize,
+}
+
+impl Drop for BorrowRefMut<'_> {
+    fn drop(&mut self) {
+        self.borrows.store(0, Ordering::Release);
+    }
+}
+
+pub(crate) struct CellRef<'a, T: ?Sized> {
+    value: &'a T,
+    borrow: BorrowRef<'a>,
+}
+
+impl<'a, T: ?Sized> CellRef<'a, T> {
+    pub fn map<U: ?Sized, F: FnOnce(&T) -> &U>(this: Self, f: F) -> CellRef<'a, U> {
+        CellRef {
+            value: f(this.value),
+            borrow: this.borrow,
+        }
+    }
+    pub fn filter_map<U: ?Sized, F: FnOnce(&T) -> Option<&U>>(
+        this: Self,
+        f: F,
+    ) -> Option<CellRef<'a, U>> {
+        Some(CellRef {
+            value: f(this.value)?,
+            borrow: this.borrow,
+        })
+    }
+}
+
+impl<T: ?Sized> Deref for CellRef<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        self.value
+    }
+}
+
+pub(crate) struct CellRefMut<'a, T: ?Sized> {
+    value: &'a mut T,
+    borrow: BorrowRefMut<'a>,
+}
+
+impl<'a, T: ?Sized> CellRefMut<'a, T> {
+    pub fn map<U: ?Sized, F: FnOnce(&mut T) -> &mut U>(this: Self, f: F) -> CellRefMut<'a, U> {
+        CellRefMut {
+            value: f(this.value),
+            borrow: this.borrow,
+        }
+    }
+    pub fn filter_map<U: ?Sized, F: FnOnce(&mut T) -> Option<&mut U>>(
+        this: Self,
+        f: F,
+    ) -> Option<CellRefMut<'a, U>> {
+        Some(CellRefMut {
+            value: f(this.value)?,
+            borrow: this.borrow,
+        })
+    }
+}
+
+impl<T: ?Sized> Deref for CellRefMut<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        self.value
+    }
+}
+
+impl<T: ?Sized> DerefMut for CellRefMut<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.value
+    }
+}
@@ -0,0 +1,67 @@
+use crate::entities::*;
+use crate::world::*;
+
+// World changes recorded by systems that cannot borrow the world mutably
+// while they run. The buffer lives in the world as a resource and is applied
+// in order by `World::apply_commands`.
+#[derive(Default)]
+pub(crate) struct Commands {
+    queue: Vec<Command>,
+}
+
+type Command = Box<dyn FnOnce(&mut World) + Send + Sync>;
+
+impl Commands {
+    pub fn add<F: FnOnce(&mut World) + Send + Sync + 'static>(&mut self, command: F) {
+        self.queue.push(Box::new(command));
+    }
+    pub fn insert<T: Send + Sync + 'static>(&mut self, entity_id: EntityID, value: T) {
+        self.add(move |world| {
+            if world.is_alive(entity_id) {
+                world.insert(entity_id, value);
+            }
+        });
+    }
+    pub fn remove<T: 'static>(&mut self, entity_id: EntityID) {
+        self.add(move |world| {
+            world.remove::<T>(entity_id);
+        });
+    }
+    pub fn apply(&mut self, world: &mut World) {
+        for command in self.queue.drain(..) {
+            command(world);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Step(u32);
+
+    // Each command sees the world as the ones queued before it left it.
+    #[test]
+    fn commands_apply_in_order() {
+        let mut world = World::default();
+        world.register::<Step>();
+        let entity_id = world.create_entity();
+        let despawned = world.create_entity();
+        let mut commands = Commands::default();
+        commands.insert(entity_id, Step(1));
+        commands.remove::<Step>(entity_id);
+        commands.insert(entity_id, Step(2));
+        commands.add(move |world| {
+            world.despawn(despawned);
+        });
+        commands.insert(despawned, Step(3));
+        assert!(world.get::<Step>(entity_id).is_none());
+
+        commands.apply(&mut world);
+        assert_eq!(world.get::<Step>(entity_id).unwrap().0, 2);
+        assert!(!world.is_alive(despawned));
+        assert!(world.get::<Step>(despawned).is_none());
+        assert_eq!(world.storage::<Step>().len(), 1);
+        assert_eq!(world.storage::<Step>().removed(), &[entity_id]);
+    }
+}
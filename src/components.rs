@@ -1,19 +1,30 @@
-use quicksilver::prelude::*;
+#[cfg(all(feature = "parallel", not(target_arch = "wasm32")))]
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
 use std::collections::*;
-use std::hash::Hash;
+use std::hash::{Hash, Hasher};
+use std::ptr;
+use std::sync::Arc;
 
-pub(crate) type EntityID = u32;
+use crate::entities::*;
+use crate::geom::*;
+use crate::hash::*;
 
+#[derive(Clone)]
 pub(crate) struct Component<T> {
     entity_id: EntityID,
     inner: T,
+    added_tick: u32,
+    changed_tick: u32,
 }
 
 impl<T> Component<T> {
-    pub fn new(entity_id: EntityID, inner: T) -> Self {
+    pub fn new(entity_id: EntityID, inner: T, tick: u32) -> Self {
         Self {
             entity_id: entity_id,
             inner: inner,
+            added_tick: tick,
+            changed_tick: tick,
         }
     }
     pub fn entity_id(&self) -> EntityID {
@@ -22,40 +33,232 @@ impl<T> Component<T> {
     pub fn inner(&self) -> &T {
         &self.inner
     }
-    pub fn inner_mut(&mut self) -> &mut T {
+    // Every mutable access counts as a change, whether or not the value is
+    // actually written.
+    pub fn inner_mut(&mut self, tick: u32) -> &mut T {
+        self.changed_tick = tick;
         &mut self.inner
     }
+    pub fn added_tick(&self) -> u32 {
+        self.added_tick
+    }
+    pub fn changed_tick(&self) -> u32 {
+        self.changed_tick
+    }
+    // Raw versions of `entity_id` and `inner_mut` that touch only the fields
+    // they need, so they can be used while a `&mut T` to the same component
+    // is alive. `component` must point to a live component.
+    pub unsafe fn entity_id_of(component: *const Self) -> EntityID {
+        ptr::addr_of!((*component).entity_id).read()
+    }
+    // The caller must not hand out a second `&mut T` to the same component
+    // while the first is alive.
+    pub unsafe fn inner_mut_of<'a>(component: *mut Self, tick: u32) -> &'a mut T {
+        ptr::addr_of_mut!((*component).changed_tick).write(tick);
+        &mut *ptr::addr_of_mut!((*component).inner)
+    }
 }
 
 pub(crate) type CContainer<T> = ComponentContainer<T>;
 
-// #[derive(Default)]
+const SPARSE_PAGE_SIZE: usize = 256;
+
+// Maps the index part of an entity id to a position in the dense component
+// vector. The sparse variant trades memory for a hash-free lookup and suits
+// components that most entities have.
+#[derive(Clone)]
+pub(crate) enum EntityIndex {
+    Hash(HashMap<u32, usize>),
+    Sparse(Vec<Option<Box<[Option<usize>; SPARSE_PAGE_SIZE]>>>),
+}
+
+impl EntityIndex {
+    pub fn get(&self, index: u32) -> Option<usize> {
+        match self {
+            EntityIndex::Hash(map) => map.get(&index).copied(),
+            EntityIndex::Sparse(pages) => {
+                let index = index as usize;
+                let page = pages.get(index / SPARSE_PAGE_SIZE)?.as_ref()?;
+                page[index % SPARSE_PAGE_SIZE]
+            }
+        }
+    }
+    pub fn insert(&mut self, index: u32, dense: usize) {
+        match self {
+            EntityIndex::Hash(map) => {
+                map.insert(index, dense);
+            }
+            EntityIndex::Sparse(pages) => {
+                let index = index as usize;
+                let page = index / SPARSE_PAGE_SIZE;
+                if pages.len() <= page {
+                    pages.resize_with(page + 1, || None);
+                }
+                let page = pages[page].get_or_insert_with(|| Box::new([None; SPARSE_PAGE_SIZE]));
+                page[index % SPARSE_PAGE_SIZE] = Some(dense);
+            }
+        }
+    }
+    pub fn remove(&mut self, index: u32) {
+        match self {
+            EntityIndex::Hash(map) => {
+                map.remove(&index);
+            }
+            EntityIndex::Sparse(pages) => {
+                let index = index as usize;
+                if let Some(Some(page)) = pages.get_mut(index / SPARSE_PAGE_SIZE) {
+                    page[index % SPARSE_PAGE_SIZE] = None;
+                }
+            }
+        }
+    }
+    pub fn clear(&mut self) {
+        match self {
+            EntityIndex::Hash(map) => map.clear(),
+            EntityIndex::Sparse(pages) => pages.clear(),
+        }
+    }
+}
+
+#[derive(Clone)]
 pub(crate) struct ComponentContainer<T> {
-    map: HashMap<EntityID, usize>,
+    map: EntityIndex,
     vec: Vec<Component<T>>,
+    removed: Vec<EntityID>,
+    tick: u32,
+    last_tick: u32,
+    // The archetype this container is a column of, if any.
+    table: Option<u32>,
 }
 
 impl<T> Default for ComponentContainer<T> {
     fn default() -> Self {
         Self {
-            map: HashMap::new(),
+            map: EntityIndex::Hash(HashMap::new()),
             vec: Vec::new(),
+            removed: Vec::new(),
+            tick: 0,
+            last_tick: 0,
+            table: None,
         }
     }
 }
 
 impl<T> ComponentContainer<T> {
+    pub fn sparse() -> Self {
+        Self {
+            map: EntityIndex::Sparse(Vec::new()),
+            ..Default::default()
+        }
+    }
+    pub fn tick(&self) -> u32 {
+        self.tick
+    }
+    pub fn set_tick(&mut self, tick: u32) {
+        self.last_tick = self.tick;
+        self.tick = tick;
+    }
+    pub fn table(&self) -> Option<u32> {
+        self.table
+    }
+    pub fn set_table(&mut self, table: Option<u32>) {
+        self.table = table;
+    }
     pub fn push(&mut self, entity_id: EntityID, item: T) {
-        self.vec.push(Component::<T>::new(entity_id, item));
-        self.map.insert(entity_id, self.vec.len() - 1);
+        if let Some(index) = self.map.get(entity_id.index()) {
+            self.vec[index] = Component::<T>::new(entity_id, item, self.tick);
+            return;
+        }
+        self.vec
+            .push(Component::<T>::new(entity_id, item, self.tick));
+        self.map.insert(entity_id.index(), self.vec.len() - 1);
+    }
+    pub fn remove(&mut self, entity_id: EntityID) -> Option<T> {
+        let index = self.index_of(entity_id)?;
+        self.map.remove(entity_id.index());
+        let removed = self.vec.swap_remove(index);
+        if let Some(moved) = self.vec.get(index) {
+            self.map.insert(moved.entity_id().index(), index);
+        }
+        self.removed.push(entity_id);
+        Some(removed.inner)
+    }
+    pub fn contains(&self, entity_id: EntityID) -> bool {
+        self.index_of(entity_id).is_some()
+    }
+    // Exchanges the positions of two components in the dense vector.
+    pub fn swap_rows(&mut self, a: usize, b: usize) {
+        if a == b {
+            return;
+        }
+        self.vec.swap(a, b);
+        self.map.insert(self.vec[a].entity_id().index(), a);
+        self.map.insert(self.vec[b].entity_id().index(), b);
+    }
+    pub fn len(&self) -> usize {
+        self.vec.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.vec.is_empty()
+    }
+    pub fn clear(&mut self) {
+        self.removed
+            .extend(self.vec.iter().map(|component| component.entity_id()));
+        self.map.clear();
+        self.vec.clear();
+    }
+    // Entities whose component was removed since the last `clear_removed`.
+    pub fn removed(&self) -> &[EntityID] {
+        &self.removed
+    }
+    pub fn clear_removed(&mut self) {
+        self.removed.clear();
     }
     pub fn get(&self, entity_id: EntityID) -> Option<&T> {
-        let index = self.map.get(&entity_id)?;
-        Some(self.vec[*index].inner())
+        let index = self.index_of(entity_id)?;
+        Some(self.vec[index].inner())
     }
     pub fn get_mut(&mut self, entity_id: EntityID) -> Option<&mut T> {
-        let index = self.map.get(&entity_id)?;
-        Some(self.vec[*index].inner_mut())
+        let index = self.index_of(entity_id)?;
+        Some(self.vec[index].inner_mut(self.tick))
+    }
+    // Added and changed report what happened during the previous tick, so
+    // every system sees a change exactly once whether it runs before or
+    // after the system that made it.
+    pub fn is_added(&self, entity_id: EntityID) -> bool {
+        match self.index_of(entity_id) {
+            Some(index) => self.in_last_tick(self.vec[index].added_tick()),
+            None => false,
+        }
+    }
+    pub fn is_changed(&self, entity_id: EntityID) -> bool {
+        match self.index_of(entity_id) {
+            Some(index) => self.in_last_tick(self.vec[index].changed_tick()),
+            None => false,
+        }
+    }
+    fn in_last_tick(&self, tick: u32) -> bool {
+        self.last_tick <= tick && tick < self.tick
+    }
+    // Lookups go through the index part of the id only, so the stored
+    // generation has to be compared to reject ids of despawned entities.
+    pub fn index_of(&self, entity_id: EntityID) -> Option<usize> {
+        let index = self.map.get(entity_id.index())?;
+        if self.vec[index].entity_id() == entity_id {
+            Some(index)
+        } else {
+            None
+        }
+    }
+    pub fn entity_at(&self, index: usize) -> EntityID {
+        self.vec[index].entity_id()
+    }
+    pub fn item_at(&self, index: usize) -> &T {
+        self.vec[index].inner()
+    }
+    // Borrows the index and the components separately, for `Write`.
+    pub fn split_mut(&mut self) -> (&EntityIndex, &mut [Component<T>]) {
+        (&self.map, &mut self.vec)
     }
     pub fn iter(&self) -> ComponentIter<T> {
         ComponentIter {
@@ -65,10 +268,28 @@ impl<T> ComponentContainer<T> {
     pub fn iter_mut(&mut self) -> ComponentIterMut<T> {
         ComponentIterMut {
             iter: self.vec.iter_mut(),
+            tick: self.tick,
         }
     }
 }
 
+// Parallel versions of `iter` and `iter_mut`, split across the rayon thread
+// pool. Worth it for systems that do a lot of work per entity.
+#[cfg(all(feature = "parallel", not(target_arch = "wasm32")))]
+impl<T: Send + Sync> ComponentContainer<T> {
+    pub fn par_iter(&self) -> impl IndexedParallelIterator<Item = (EntityID, &T)> + '_ {
+        self.vec
+            .par_iter()
+            .map(|component| (component.entity_id(), component.inner()))
+    }
+    pub fn par_iter_mut(&mut self) -> impl IndexedParallelIterator<Item = (EntityID, &mut T)> + '_ {
+        let tick = self.tick;
+        self.vec
+            .par_iter_mut()
+            .map(move |component| (component.entity_id(), component.inner_mut(tick)))
+    }
+}
+
 pub(crate) struct ComponentIter<'a, T>
 where
     T: 'a,
@@ -82,228 +303,110 @@ impl<'a, T> Iterator for ComponentIter<'a, T> {
         Some((next.entity_id(), next.inner()))
     }
 }
-impl<'a, T> ComponentIter<'a, T> {
-    pub fn zip_entity<U>(self, other: &'a CContainer<U>) -> ZipEntity<'a, T, U> {
-        ZipEntity {
-            base: self,
-            other: other,
-        }
-    }
-    pub fn zip_entity2<U, V>(
-        self,
-        other1: &'a CContainer<U>,
-        other2: &'a CContainer<V>,
-    ) -> ZipEntity2<'a, T, U, V> {
-        ZipEntity2 {
-            base: self,
-            other1: other1,
-            other2: other2,
-        }
-    }
-}
 pub(crate) struct ComponentIterMut<'a, T>
 where
     T: 'a,
 {
     iter: std::slice::IterMut<'a, Component<T>>,
+    tick: u32,
 }
 impl<'a, T> Iterator for ComponentIterMut<'a, T> {
     type Item = (EntityID, &'a mut T);
     fn next(&mut self) -> Option<Self::Item> {
         let next = self.iter.next()?;
-        Some((next.entity_id(), next.inner_mut()))
+        Some((next.entity_id(), next.inner_mut(self.tick)))
     }
 }
-impl<'a, T> ComponentIterMut<'a, T> {
-    pub fn zip_entity<U>(self, other: &'a CContainer<U>) -> ZipEntityMut<'a, T, U> {
-        ZipEntityMut {
-            base: self,
-            other: other,
-        }
-    }
-    pub fn zip_entity2<U, V>(
-        self,
-        other1: &'a CContainer<U>,
-        other2: &'a CContainer<V>,
-    ) -> ZipEntity2Mut<'a, T, U, V> {
-        ZipEntity2Mut {
-            base: self,
-            other1: other1,
-            other2: other2,
-        }
-    }
 
-    pub fn zip_entity3<U, V, W>(
-        self,
-        other1: &'a CContainer<U>,
-        other2: &'a CContainer<V>,
-        other3: &'a CContainer<W>,
-    ) -> ZipEntity3Mut<'a, T, U, V, W> {
-        ZipEntity3Mut {
-            base: self,
-            other1: other1,
-            other2: other2,
-            other3: other3,
-        }
-    }
+#[derive(Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct Team {
+    team_id: u32,
 }
 
-pub(crate) struct ZipEntity<'a, T, U>
-where
-    T: 'a,
-    U: 'a,
-{
-    base: ComponentIter<'a, T>,
-    other: &'a CContainer<U>,
+impl Team {
+    pub fn new(team_id: u32) -> Self {
+        Self { team_id: team_id }
+    }
+    pub fn team_id(&self) -> u32 {
+        self.team_id
+    }
 }
 
-impl<'a, T, U> Iterator for ZipEntity<'a, T, U> {
-    type Item = (EntityID, &'a T, &'a U);
-    fn next(&mut self) -> Option<Self::Item> {
-        while let Some((entity_id, base)) = self.base.next() {
-            if let Some(other_item) = self.other.get(entity_id) {
-                return Some((entity_id, base, other_item));
-            }
-        }
-        None
+impl StateHash for Team {
+    fn hash_state(&self, hasher: &mut StateHasher) {
+        self.team_id.hash_state(hasher);
     }
 }
 
-pub(crate) struct ZipEntityMut<'a, T, U>
-where
-    T: 'a,
-    U: 'a,
-{
-    base: ComponentIterMut<'a, T>,
-    other: &'a CContainer<U>,
+#[derive(Default, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Input {
+    pub left: bool,
+    pub right: bool,
+    pub up: bool,
+    pub down: bool,
+    pub attack: bool,
 }
 
-impl<'a, T, U> Iterator for ZipEntityMut<'a, T, U> {
-    type Item = (EntityID, &'a mut T, &'a U);
-    fn next(&mut self) -> Option<Self::Item> {
-        while let Some((entity_id, base)) = self.base.next() {
-            if let Some(other_item) = self.other.get(entity_id) {
-                return Some((entity_id, base, other_item));
-            }
-        }
-        None
+impl StateHash for Input {
+    fn hash_state(&self, hasher: &mut StateHasher) {
+        self.left.hash_state(hasher);
+        self.right.hash_state(hasher);
+        self.up.hash_state(hasher);
+        self.down.hash_state(hasher);
+        self.attack.hash_state(hasher);
     }
 }
-pub(crate) struct ZipEntity2<'a, T, U, V>
-where
-    T: 'a,
-    U: 'a,
-    V: 'a,
-{
-    base: ComponentIter<'a, T>,
-    other1: &'a CContainer<U>,
-    other2: &'a CContainer<V>,
-}
 
-impl<'a, T, U, V> Iterator for ZipEntity2<'a, T, U, V> {
-    type Item = (EntityID, &'a T, &'a U, &'a V);
-    fn next(&mut self) -> Option<Self::Item> {
-        while let Some((entity_id, base)) = self.base.next() {
-            let other1_item = self.other1.get(entity_id);
-            let other2_item = self.other2.get(entity_id);
-            if other1_item.is_some() && other2_item.is_some() {
-                return Some((entity_id, base, other1_item.unwrap(), other2_item.unwrap()));
+// Keyboard state written by `Game::event` or `Simulation::set_input` and
+// copied into every `Input` component at the start of a frame. While a
+// recording is replayed, each entity gets its recorded input instead.
+#[derive(Default)]
+pub(crate) struct InputState {
+    pub keys: Input,
+    pub replayed: Option<HashMap<EntityID, Input>>,
+}
+
+// The world keys storages by type, so plain aliases of `Vector` would all
+// share one container. Each of these wraps its value in a distinct type.
+macro_rules! newtype_component {
+    ($name:ident, $inner:ty) => {
+        #[derive(Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+        pub(crate) struct $name(pub $inner);
+
+        impl std::ops::Deref for $name {
+            type Target = $inner;
+            fn deref(&self) -> &$inner {
+                &self.0
             }
         }
-        None
-    }
-}
-
-pub(crate) struct ZipEntity2Mut<'a, T, U, V>
-where
-    T: 'a,
-    U: 'a,
-    V: 'a,
-{
-    base: ComponentIterMut<'a, T>,
-    other1: &'a CContainer<U>,
-    other2: &'a CContainer<V>,
-}
-
-impl<'a, T, U, V> Iterator for ZipEntity2Mut<'a, T, U, V> {
-    type Item = (EntityID, &'a mut T, &'a U, &'a V);
-    fn next(&mut self) -> Option<Self::Item> {
-        while let Some((entity_id, base)) = self.base.next() {
-            let other1_item = self.other1.get(entity_id);
-            let other2_item = self.other2.get(entity_id);
-            if other1_item.is_some() && other2_item.is_some() {
-                return Some((entity_id, base, other1_item.unwrap(), other2_item.unwrap()));
+        impl std::ops::DerefMut for $name {
+            fn deref_mut(&mut self) -> &mut $inner {
+                &mut self.0
             }
         }
-        None
-    }
-}
-
-pub(crate) struct ZipEntity3Mut<'a, T, U, V, W>
-where
-    T: 'a,
-    U: 'a,
-    V: 'a,
-    W: 'a,
-{
-    base: ComponentIterMut<'a, T>,
-    other1: &'a CContainer<U>,
-    other2: &'a CContainer<V>,
-    other3: &'a CContainer<W>,
-}
-
-impl<'a, T, U, V, W> Iterator for ZipEntity3Mut<'a, T, U, V, W> {
-    type Item = (&'a mut T, &'a U, &'a V, &'a W);
-    fn next(&mut self) -> Option<Self::Item> {
-        while let Some((entity_id, base)) = self.base.next() {
-            let other1_item = self.other1.get(entity_id);
-            let other2_item = self.other2.get(entity_id);
-            let other3_item = self.other3.get(entity_id);
-            if other1_item.is_some() && other2_item.is_some() && other3_item.is_some() {
-                return Some((
-                    base,
-                    other1_item.unwrap(),
-                    other2_item.unwrap(),
-                    other3_item.unwrap(),
-                ));
+        impl StateHash for $name {
+            fn hash_state(&self, hasher: &mut StateHasher) {
+                self.0.hash_state(hasher);
             }
         }
-        None
-    }
-}
-
-#[derive(Default)]
-pub(crate) struct Team {
-    team_id: u32,
-}
-
-impl Team {
-    pub fn new(team_id: u32) -> Self {
-        Self { team_id: team_id }
-    }
-    pub fn team_id(&self) -> u32 {
-        self.team_id
-    }
+    };
 }
 
-#[derive(Default)]
-pub(crate) struct Input {
-    pub left: bool,
-    pub right: bool,
-    pub up: bool,
-    pub down: bool,
-    pub attack: bool,
-}
+newtype_component!(MoveTarget, Vector);
 
-pub(crate) type MoveTarget = Vector;
+newtype_component!(Velocity, Vector);
 
-pub(crate) type Velocity = Vector;
+newtype_component!(Position, Vector);
 
-pub(crate) type Position = Vector;
+// `Position` as of the start of the current step, for drawing in between.
+newtype_component!(PreviousPosition, Vector);
 
-pub(crate) type Direction = f32;
+newtype_component!(Direction, f32);
 
-#[derive(Default)]
+#[derive(Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub(crate) struct CharacterView {
     pub position: Vector,
     pub direction: f32,
@@ -313,26 +416,31 @@ pub(crate) struct CharacterView {
     pub weapon_direction: f32,
 }
 
-#[derive(Default, Clone)]
+#[derive(Default, Clone, Serialize, Deserialize)]
 pub(crate) struct CharacterAnimFrame {
     pub radius_scale: f32,
     pub weapon_direction: f32,
+    // Speed along the facing direction, in pixels per second.
     pub move_forward: f32,
 }
 
-#[derive(Default)]
+// Animations are kept in key order so that saving an animator gives the same
+// output every time. They are shared between clones, which keeps world
+// snapshots from copying every frame of every animation.
+#[derive(Default, Clone, Serialize, Deserialize)]
 pub(crate) struct Animator<K, V>
 where
-    K: Hash + Eq,
+    K: Ord,
 {
     playing_id: Option<K>,
     current_frame: usize,
-    animations: HashMap<K, Animation<V>>,
+    animations: Arc<BTreeMap<K, Animation<V>>>,
 }
 
 impl<K, V> Animator<K, V>
 where
-    K: Hash + Eq + Copy,
+    K: Ord + Copy,
+    V: Clone,
 {
     pub fn play(&mut self, animation_id: K) {
         if self.animations.contains_key(&animation_id) {
@@ -358,7 +466,7 @@ where
         }
     }
     pub fn register(&mut self, id: K, anim: Animation<V>) {
-        self.animations.insert(id, anim);
+        Arc::make_mut(&mut self.animations).insert(id, anim);
     }
     pub fn value(&self) -> Option<&V> {
         let id = self.playing_id?;
@@ -370,7 +478,18 @@ where
     }
 }
 
-#[derive(Default)]
+// The animations themselves never change, so only the playing frame counts.
+impl<K, V> StateHash for Animator<K, V>
+where
+    K: Ord + std::hash::Hash,
+{
+    fn hash_state(&self, hasher: &mut StateHasher) {
+        std::hash::Hash::hash(&self.playing_id, hasher);
+        std::hash::Hash::hash(&self.current_frame, hasher);
+    }
+}
+
+#[derive(Default, Clone, Serialize, Deserialize)]
 pub(crate) struct Animation<T> {
     looped: bool,
     values: Vec<T>,
@@ -387,70 +506,95 @@ impl<T> Animation<T> {
 //     pub hit: bool,
 // }
 
-#[derive(Default)]
+#[derive(Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub(crate) struct SwordCollider {
     pub active: bool,
-    pub line: quicksilver::geom::Line,
+    pub line: Line,
 }
 impl SwordCollider {
     pub fn is_collided(&self, body: &BodyDefenseCollider) -> bool {
         if self.active == false {
             false
         } else {
-            body.circle.overlaps(&self.line)
+            body.circle.overlaps_line(&self.line)
         }
     }
+    // Point on the blade closest to the center of the body.
+    pub fn hit_point(&self, body: &BodyDefenseCollider) -> Vector {
+        self.line.closest_point(body.circle.pos)
+    }
 }
 
-#[derive(Default)]
+impl StateHash for SwordCollider {
+    fn hash_state(&self, hasher: &mut StateHasher) {
+        self.active.hash_state(hasher);
+        self.line.hash_state(hasher);
+    }
+}
+
+#[derive(Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub(crate) struct BodyWeaponCollider {
-    pub circle: quicksilver::geom::Circle,
+    pub circle: Circle,
 }
 
 impl BodyWeaponCollider {
     pub fn is_collided(&self, body: &BodyDefenseCollider) -> bool {
-        body.circle.overlaps(&self.circle)
+        body.circle.overlaps_circle(&self.circle)
+    }
+    // Point on the edge of the body facing the attacker.
+    pub fn hit_point(&self, body: &BodyDefenseCollider) -> Vector {
+        let (from, to) = (body.circle.pos, self.circle.pos);
+        let distance = from.distance(to);
+        if distance == 0f32 {
+            return from;
+        }
+        let scale = body.circle.radius / distance;
+        Vector::new(
+            from.x + (to.x - from.x) * scale,
+            from.y + (to.y - from.y) * scale,
+        )
     }
 }
 
-#[derive(Default)]
-pub(crate) struct BodyDefenseCollider {
-    pub hit: bool,
-    pub circle: quicksilver::geom::Circle,
+impl StateHash for BodyWeaponCollider {
+    fn hash_state(&self, hasher: &mut StateHasher) {
+        self.circle.hash_state(hasher);
+    }
 }
 
-pub(crate) struct ValueObserver<V, C> {
-    prev_changed: bool,
-    changed: bool,
-    value: V,
-    check_fn: fn(&C) -> V,
+#[derive(Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct BodyDefenseCollider {
+    pub circle: Circle,
 }
 
-impl<V, C> ValueObserver<V, C>
-where
-    V: PartialEq + Copy,
-{
-    pub fn new(value: V, check_fn: fn(&C) -> V) -> Self {
-        Self {
-            prev_changed: false,
-            changed: false,
-            value: value,
-            check_fn: check_fn,
-        }
-    }
-    pub fn set(&mut self, component: &C) {
-        self.prev_changed = self.changed;
-        self.changed = false;
-        self.value = (self.check_fn)(component);
-    }
-    pub fn check(&mut self, component: &C) -> bool {
-        self.changed = (self.check_fn)(component) != self.value;
-        self.changed
+impl StateHash for BodyDefenseCollider {
+    fn hash_state(&self, hasher: &mut StateHasher) {
+        self.circle.hash_state(hasher);
     }
-    pub fn value(&self) -> V {
-        self.value
-    }
-    pub fn is_changed(&self) -> bool {
-        self.prev_changed
+}
+
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub(crate) enum Weapon {
+    Sword,
+    Body,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct HitEvent {
+    pub attacker: EntityID,
+    pub defender: EntityID,
+    pub weapon: Weapon,
+    pub point: Vector,
+}
+
+impl StateHash for HitEvent {
+    fn hash_state(&self, hasher: &mut StateHasher) {
+        self.attacker.hash(hasher);
+        self.defender.hash(hasher);
+        hasher.write_u8(self.weapon as u8);
+        self.point.hash_state(hasher);
     }
 }
@@ -0,0 +1,74 @@
+use crate::hash::*;
+use serde::{Deserialize, Serialize};
+use std::hash::Hasher;
+
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub(crate) struct EntityID {
+    index: u32,
+    generation: u32,
+}
+
+impl EntityID {
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+}
+
+// Hands out entity ids, recycling the index of a despawned entity with a
+// bumped generation so that ids held past despawn never match the new owner.
+#[derive(Default, Clone, Serialize, Deserialize)]
+pub(crate) struct EntityAllocator {
+    generations: Vec<u32>,
+    alive: Vec<bool>,
+    free: Vec<u32>,
+}
+
+impl EntityAllocator {
+    pub fn allocate(&mut self) -> EntityID {
+        if let Some(index) = self.free.pop() {
+            self.alive[index as usize] = true;
+            return EntityID {
+                index: index,
+                generation: self.generations[index as usize],
+            };
+        }
+        self.generations.push(0);
+        self.alive.push(true);
+        EntityID {
+            index: (self.generations.len() - 1) as u32,
+            generation: 0,
+        }
+    }
+    pub fn free(&mut self, entity_id: EntityID) -> bool {
+        if !self.is_alive(entity_id) {
+            return false;
+        }
+        let index = entity_id.index as usize;
+        self.alive[index] = false;
+        self.generations[index] = self.generations[index].wrapping_add(1);
+        self.free.push(entity_id.index);
+        true
+    }
+    pub fn is_alive(&self, entity_id: EntityID) -> bool {
+        let index = entity_id.index as usize;
+        index < self.alive.len()
+            && self.alive[index]
+            && self.generations[index] == entity_id.generation
+    }
+}
+
+// Which ids are alive and which indices get reused, and in what order, decide
+// the ids of every entity spawned later.
+impl StateHash for EntityAllocator {
+    fn hash_state(&self, hasher: &mut StateHasher) {
+        hasher.write_usize(self.generations.len());
+        for (generation, alive) in self.generations.iter().zip(&self.alive) {
+            generation.hash_state(hasher);
+            alive.hash_state(hasher);
+        }
+        hasher.write_usize(self.free.len());
+        for index in &self.free {
+            index.hash_state(hasher);
+        }
+    }
+}
@@ -0,0 +1,102 @@
+use crate::hash::*;
+use serde::{Deserialize, Serialize};
+use std::hash::Hasher;
+use std::marker::PhantomData;
+
+// Double-buffered event channel. Events stay readable for the frame they are
+// sent in and the next one, so a reader that runs before the sender in a
+// frame still gets them, and `update` drops them after that.
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct Events<E> {
+    previous: Vec<E>,
+    current: Vec<E>,
+    previous_start: usize,
+    current_start: usize,
+}
+
+impl<E> Default for Events<E> {
+    fn default() -> Self {
+        Self {
+            previous: Vec::new(),
+            current: Vec::new(),
+            previous_start: 0,
+            current_start: 0,
+        }
+    }
+}
+
+impl<E> Events<E> {
+    pub fn send(&mut self, event: E) {
+        self.current.push(event);
+    }
+    // Swaps the buffers. Called once at the start of every frame.
+    pub fn update(&mut self) {
+        std::mem::swap(&mut self.previous, &mut self.current);
+        self.current.clear();
+        self.previous_start = self.current_start;
+        self.current_start = self.previous_start + self.previous.len();
+    }
+    fn event_count(&self) -> usize {
+        self.current_start + self.current.len()
+    }
+}
+
+// Cursor into an `Events<E>` channel. `S` tags the reader so that several
+// systems can each keep their own cursor over the same event type.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct EventReader<E, S> {
+    last_event_count: usize,
+    #[serde(skip)]
+    phantom: PhantomData<fn() -> (E, S)>,
+}
+
+impl<E, S> Default for EventReader<E, S> {
+    fn default() -> Self {
+        Self {
+            last_event_count: 0,
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<E, S> Clone for EventReader<E, S> {
+    fn clone(&self) -> Self {
+        Self {
+            last_event_count: self.last_event_count,
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<E, S> EventReader<E, S> {
+    // Events sent since the last call, oldest first.
+    pub fn read<'a>(&mut self, events: &'a Events<E>) -> impl Iterator<Item = &'a E> {
+        let previous_skip = self.last_event_count.saturating_sub(events.previous_start);
+        let current_skip = self.last_event_count.saturating_sub(events.current_start);
+        self.last_event_count = events.event_count();
+        events
+            .previous
+            .iter()
+            .skip(previous_skip)
+            .chain(events.current.iter().skip(current_skip))
+    }
+}
+
+impl<E: StateHash> StateHash for Events<E> {
+    fn hash_state(&self, hasher: &mut StateHasher) {
+        hasher.write_usize(self.previous_start);
+        hasher.write_usize(self.current_start);
+        for events in &[&self.previous, &self.current] {
+            hasher.write_usize(events.len());
+            for event in events.iter() {
+                event.hash_state(hasher);
+            }
+        }
+    }
+}
+
+impl<E, S> StateHash for EventReader<E, S> {
+    fn hash_state(&self, hasher: &mut StateHasher) {
+        hasher.write_usize(self.last_event_count);
+    }
+}
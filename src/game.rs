@@ -0,0 +1,189 @@
+use crate::components::*;
+use crate::query::*;
+use crate::replay::*;
+use crate::simulation::*;
+use crate::systems::*;
+use crate::time::*;
+use crate::world::*;
+use quicksilver::prelude::*;
+use std::error::Error;
+
+// The quicksilver front end: feeds keyboard input and real time into a
+// `Simulation` and draws it.
+struct Game {
+    simulation: Simulation,
+    clock: Clock,
+    quick_snapshot: Option<Snapshot>,
+}
+
+const SAVE_PATH: &str = "save.json";
+const RECORDING_PATH: &str = "recording.json";
+
+impl State for Game {
+    fn new() -> Result<Game> {
+        let simulation =
+            Simulation::new().map_err(|err| quicksilver::Error::ContextError(err.to_string()))?;
+        Ok(Self {
+            simulation: simulation,
+            clock: Clock::default(),
+            quick_snapshot: None,
+        })
+    }
+
+    /// Will happen at a fixed rate of 60 ticks per second under ideal conditions. Under non-ideal conditions,
+    /// the game loop will do its best to still call the update at about 60 TPS.
+    ///
+    /// Runs as many fixed steps as the real time since the last update calls
+    /// for, so the game keeps the same speed when updates are late.
+    fn update(&mut self, window: &mut Window) -> Result<()> {
+        let real_delta = self.clock.delta(window.update_rate() as f32 / 1000f32);
+        let steps = self
+            .simulation
+            .world()
+            .resource_mut::<Time>()
+            .advance(real_delta);
+        for _ in 0..steps {
+            self.simulation.step();
+            match self.simulation.finish_replay() {
+                Some(Ok(())) => log::info!("replay finished in the recorded state"),
+                Some(Err(err)) => log::error!("{}", err),
+                None => {}
+            }
+        }
+        Ok(())
+    }
+    /// Process an incoming event
+    ///
+    /// By default it does nothing
+    ///
+    /// WASD moves and Space attacks. The function keys are debugging aids:
+    /// F5/F9 save to and load from `SAVE_PATH`, F6/F10 take and restore an
+    /// in-memory snapshot, F7 starts and stops recording to `RECORDING_PATH`
+    /// and F8 replays that recording.
+    fn event(&mut self, event: &Event, _: &mut Window) -> Result<()> {
+        match event {
+            Event::Key(Key::F5, ButtonState::Pressed) => {
+                if let Err(err) = self.simulation.save(SAVE_PATH) {
+                    log::error!("failed to save: {}", err);
+                }
+            }
+            Event::Key(Key::F9, ButtonState::Pressed) => {
+                if let Err(err) = self.simulation.load(SAVE_PATH) {
+                    log::error!("failed to load: {}", err);
+                }
+            }
+            Event::Key(Key::F6, ButtonState::Pressed) => {
+                self.quick_snapshot = Some(self.simulation.snapshot());
+            }
+            Event::Key(Key::F10, ButtonState::Pressed) => {
+                if let Some(snapshot) = &self.quick_snapshot {
+                    self.simulation.restore(snapshot);
+                }
+            }
+            Event::Key(Key::F7, ButtonState::Pressed) => {
+                if let Err(err) = self.toggle_recording() {
+                    log::error!("failed to record: {}", err);
+                }
+            }
+            Event::Key(Key::F8, ButtonState::Pressed) => {
+                let result = Recording::load(RECORDING_PATH)
+                    .and_then(|recording| self.simulation.start_replay(recording));
+                if let Err(err) = result {
+                    log::error!("failed to replay: {}", err);
+                }
+            }
+            Event::Key(key, state) => {
+                let mut pressed = false;
+                if *state == ButtonState::Pressed {
+                    pressed = true;
+                } else if *state == ButtonState::Released {
+                    pressed = false;
+                }
+                let mut input_state = self.simulation.world().resource_mut::<InputState>();
+                match key {
+                    Key::A => input_state.keys.left = pressed,
+                    Key::D => input_state.keys.right = pressed,
+                    Key::W => input_state.keys.up = pressed,
+                    Key::S => input_state.keys.down = pressed,
+                    Key::Space => input_state.keys.attack = pressed,
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn draw(&mut self, window: &mut Window) -> Result<()> {
+        window.clear(Color::WHITE)?;
+        let world = self.simulation.world();
+        System::process(
+            window,
+            &(
+                &*world.storage::<CharacterView>(),
+                &*world.storage::<PreviousPosition>(),
+                &*world.resource::<Time>(),
+            ),
+        );
+        Ok(())
+    }
+}
+
+impl Game {
+    // F7 starts recording, and pressing it again writes the recording to
+    // `RECORDING_PATH` for F8 to replay.
+    fn toggle_recording(&mut self) -> std::result::Result<(), Box<dyn Error>> {
+        match self.simulation.stop_recording() {
+            Some(recording) => {
+                recording.save(RECORDING_PATH)?;
+                log::info!("recorded {} steps", recording.ticks());
+            }
+            None => self.simulation.start_recording()?,
+        }
+        Ok(())
+    }
+}
+
+// Views are drawn between the positions of the last two steps, so movement
+// stays smooth when frames and steps do not line up.
+impl SystemProcess
+    for System<
+        Window,
+        (
+            &CContainer<CharacterView>,
+            &CContainer<PreviousPosition>,
+            &Time,
+        ),
+    >
+{
+    fn process(window: &mut Self::Update, (views, previous_positions, time): &Self::Refer) {
+        (*views, Optional(*previous_positions))
+            .query()
+            .for_each(|(_, view, previous)| {
+                let mut position = view.position;
+                if let Some(previous) = previous {
+                    position = **previous + (view.position - **previous) * time.alpha();
+                }
+                let color = Color {
+                    r: view.color.r,
+                    g: view.color.g,
+                    b: view.color.b,
+                    a: view.color.a,
+                };
+                window.draw(
+                    &Circle::new((position.x, position.y), view.radius * view.radius_scale),
+                    Col(color),
+                );
+                let dir = view.direction + view.weapon_direction;
+                let line_end = (
+                    position.x + dir.cos() * view.radius * 1.8f32,
+                    position.y + dir.sin() * view.radius * 1.8f32,
+                );
+                window.draw(&Line::new((position.x, position.y), line_end), Col(color));
+            });
+    }
+}
+
+pub fn run() {
+    quicksilver::lifecycle::run::<Game>("Game", Vector::new(800, 600), Settings::default());
+}
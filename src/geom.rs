@@ -0,0 +1,89 @@
+use serde::{Deserialize, Serialize};
+use std::ops::{Add, Mul, Sub};
+
+// The shapes the simulation works with. They mirror the quicksilver ones,
+// field for field, so saves and prefabs read the same, but keep the
+// simulation buildable without a window. The front end converts them for
+// drawing.
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub(crate) struct Vector {
+    pub x: f32,
+    pub y: f32,
+}
+
+impl Vector {
+    pub fn new(x: f32, y: f32) -> Self {
+        Self { x: x, y: y }
+    }
+    pub fn distance(self, other: Vector) -> f32 {
+        let d = self - other;
+        (d.x * d.x + d.y * d.y).sqrt()
+    }
+}
+
+impl Add for Vector {
+    type Output = Vector;
+    fn add(self, other: Vector) -> Vector {
+        Vector::new(self.x + other.x, self.y + other.y)
+    }
+}
+
+impl Sub for Vector {
+    type Output = Vector;
+    fn sub(self, other: Vector) -> Vector {
+        Vector::new(self.x - other.x, self.y - other.y)
+    }
+}
+
+impl Mul<f32> for Vector {
+    type Output = Vector;
+    fn mul(self, scale: f32) -> Vector {
+        Vector::new(self.x * scale, self.y * scale)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub(crate) struct Circle {
+    pub pos: Vector,
+    pub radius: f32,
+}
+
+impl Circle {
+    pub fn overlaps_circle(&self, other: &Circle) -> bool {
+        self.pos.distance(other.pos) < self.radius + other.radius
+    }
+    pub fn overlaps_line(&self, line: &Line) -> bool {
+        self.pos.distance(line.closest_point(self.pos)) < self.radius
+    }
+}
+
+// A segment from `a` to `b`. `t` is the thickness it is drawn with.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub(crate) struct Line {
+    pub a: Vector,
+    pub b: Vector,
+    pub t: f32,
+}
+
+impl Line {
+    // The point on the segment nearest to `point`.
+    pub fn closest_point(&self, point: Vector) -> Vector {
+        let ab = self.b - self.a;
+        let len2 = ab.x * ab.x + ab.y * ab.y;
+        if len2 == 0f32 {
+            return self.a;
+        }
+        let ap = point - self.a;
+        let t = ((ap.x * ab.x + ap.y * ab.y) / len2).clamp(0f32, 1f32);
+        self.a + ab * t
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub(crate) struct Color {
+    pub r: f32,
+    pub g: f32,
+    pub b: f32,
+    pub a: f32,
+}
@@ -0,0 +1,95 @@
+use crate::geom::{Circle, Line, Vector};
+use std::hash::Hasher;
+
+// 64-bit FNV-1a. Unlike `DefaultHasher` its output is fixed, and integers go
+// in little-endian at a fixed width, so the same state hashes the same on
+// every platform and build, including the 32-bit web one.
+pub(crate) struct StateHasher {
+    hash: u64,
+}
+
+impl Default for StateHasher {
+    fn default() -> Self {
+        Self {
+            hash: 0xcbf2_9ce4_8422_2325,
+        }
+    }
+}
+
+impl Hasher for StateHasher {
+    fn finish(&self) -> u64 {
+        self.hash
+    }
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.hash ^= *byte as u64;
+            self.hash = self.hash.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+    fn write_u16(&mut self, i: u16) {
+        self.write(&i.to_le_bytes());
+    }
+    fn write_u32(&mut self, i: u32) {
+        self.write(&i.to_le_bytes());
+    }
+    fn write_u64(&mut self, i: u64) {
+        self.write(&i.to_le_bytes());
+    }
+    fn write_usize(&mut self, i: usize) {
+        self.write_u64(i as u64);
+    }
+}
+
+// The part of a component or resource that `World::state_hash` covers. Anything that can
+// change what later steps do belongs in it; data that never changes or only
+// affects drawing can be left out. Floats are hashed by their bits, so even
+// the smallest drift shows up.
+pub(crate) trait StateHash {
+    fn hash_state(&self, hasher: &mut StateHasher);
+}
+
+impl StateHash for bool {
+    fn hash_state(&self, hasher: &mut StateHasher) {
+        hasher.write_u8(*self as u8);
+    }
+}
+
+impl StateHash for u32 {
+    fn hash_state(&self, hasher: &mut StateHasher) {
+        hasher.write_u32(*self);
+    }
+}
+
+impl StateHash for i32 {
+    fn hash_state(&self, hasher: &mut StateHasher) {
+        hasher.write_i32(*self);
+    }
+}
+
+impl StateHash for f32 {
+    fn hash_state(&self, hasher: &mut StateHasher) {
+        hasher.write_u32(self.to_bits());
+    }
+}
+
+impl StateHash for Vector {
+    fn hash_state(&self, hasher: &mut StateHasher) {
+        self.x.hash_state(hasher);
+        self.y.hash_state(hasher);
+    }
+}
+
+impl StateHash for Circle {
+    fn hash_state(&self, hasher: &mut StateHasher) {
+        self.pos.hash_state(hasher);
+        self.radius.hash_state(hasher);
+    }
+}
+
+impl StateHash for Line {
+    fn hash_state(&self, hasher: &mut StateHasher) {
+        self.a.hash_state(hasher);
+        self.b.hash_state(hasher);
+        self.t.hash_state(hasher);
+    }
+}
@@ -0,0 +1,47 @@
+use serde::{Deserialize, Serialize};
+use std::f32::consts::*;
+mod archetype;
+mod builder;
+mod cell;
+mod commands;
+mod components;
+mod entities;
+mod events;
+#[cfg(feature = "window")]
+mod game;
+mod geom;
+mod hash;
+mod prefab;
+mod query;
+mod registry;
+mod replay;
+mod scene;
+mod schedule;
+mod simulation;
+mod systems;
+mod time;
+mod world;
+
+use components::*;
+
+#[derive(Clone, Copy, Hash, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub(crate) enum CharacterAnimID {
+    Wait,
+    Attack,
+    Damaged,
+}
+
+impl Default for CharacterAnimID {
+    fn default() -> Self {
+        CharacterAnimID::Wait
+    }
+}
+
+pub(crate) type CharacterAnimator = Animator<CharacterAnimID, CharacterAnimFrame>;
+
+pub use components::Input;
+#[cfg(feature = "window")]
+pub use game::run;
+pub use replay::{Recording, ReplayError};
+pub use simulation::Simulation;
+pub use world::Snapshot;
@@ -0,0 +1,258 @@
+use crate::builder::*;
+use crate::entities::*;
+use crate::world::*;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::*;
+use std::fmt;
+
+#[derive(Debug)]
+pub(crate) enum PrefabError {
+    Io(std::io::Error),
+    Json(serde_json::Error),
+    UnknownPrefab(String),
+    UnknownComponent(String),
+    InvalidComponent { component: String, message: String },
+}
+
+impl fmt::Display for PrefabError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PrefabError::Io(err) => write!(f, "i/o error: {}", err),
+            PrefabError::Json(err) => write!(f, "invalid json: {}", err),
+            PrefabError::UnknownPrefab(name) => write!(f, "unknown prefab `{}`", name),
+            PrefabError::UnknownComponent(name) => write!(f, "unknown component `{}`", name),
+            PrefabError::InvalidComponent { component, message } => {
+                write!(f, "invalid `{}`: {}", component, message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for PrefabError {}
+
+impl From<std::io::Error> for PrefabError {
+    fn from(err: std::io::Error) -> Self {
+        PrefabError::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for PrefabError {
+    fn from(err: serde_json::Error) -> Self {
+        PrefabError::Json(err)
+    }
+}
+
+type ComponentLoader = Box<dyn Fn(&Value, &mut World, EntityID) -> Result<(), PrefabError>>;
+type ComponentSaver =
+    Box<dyn Fn(&World, EntityID, Option<&Value>) -> Result<Option<Value>, PrefabError>>;
+
+struct ComponentEntry {
+    load: ComponentLoader,
+    // Only components registered with `register` can be written back out.
+    save: Option<ComponentSaver>,
+}
+
+// Name of the prefab an entity was spawned from, so scenes can be saved back
+// as prefab instances.
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct PrefabName(pub String);
+
+// Named entity templates read from JSON. A file is an object of prefabs, and
+// each prefab is an object mapping component names to component data:
+//
+//     { "enemy": { "Team": { "team_id": 1 }, "Direction": 0 } }
+//
+// Component names are resolved through loaders registered up front, so a new
+// prefab only needs code when it uses a component nobody registered yet.
+#[derive(Default)]
+pub(crate) struct Prefabs {
+    components: HashMap<String, ComponentEntry>,
+    prefabs: HashMap<String, Map<String, Value>>,
+}
+
+impl Prefabs {
+    // Registers `T` under `name`, deserialized straight from the prefab data.
+    pub fn register<T: Serialize + DeserializeOwned + Send + Sync + 'static>(
+        &mut self,
+        name: &str,
+    ) {
+        let entry = ComponentEntry {
+            load: Box::new(|value, world, entity_id| {
+                world.insert(entity_id, T::deserialize(value)?);
+                Ok(())
+            }),
+            save: Some(Box::new(|world, entity_id, prefab_value| {
+                if !world.is_registered::<T>() {
+                    return Ok(None);
+                }
+                // A component the prefab has but the entity lost is saved as
+                // `null`, which removes it again on spawn.
+                let current = match (world.get::<T>(entity_id), prefab_value) {
+                    (Some(component), _) => serde_json::to_value(&*component)?,
+                    (None, Some(_)) => return Ok(Some(Value::Null)),
+                    (None, None) => return Ok(None),
+                };
+                // Round-trip the prefab data through `T` so that fields it
+                // leaves to their defaults compare equal.
+                let base = match prefab_value {
+                    Some(value) => serde_json::to_value(T::deserialize(value)?)?,
+                    None => Value::Null,
+                };
+                Ok(diff(&base, &current))
+            })),
+        };
+        self.components.insert(name.to_string(), entry);
+    }
+    // Registers a loader for components that are not plain data, such as
+    // animators built from a named animation set.
+    pub fn register_with<F>(&mut self, name: &str, loader: F)
+    where
+        F: Fn(&Value, &mut World, EntityID) -> Result<(), PrefabError> + 'static,
+    {
+        let entry = ComponentEntry {
+            load: Box::new(loader),
+            save: None,
+        };
+        self.components.insert(name.to_string(), entry);
+    }
+    // Registers the components of the bundle `B` under one `name`. Like
+    // `register_with`, they are saved back out one by one, under their own
+    // names if they are registered too.
+    pub fn register_bundle<B: Bundle + DeserializeOwned>(&mut self, name: &str) {
+        self.register_with(name, |value, world, entity_id| {
+            EntityBuilder::from_id(world, entity_id).with_bundle(B::deserialize(value)?);
+            Ok(())
+        });
+    }
+    // Adds every prefab in `json`, replacing prefabs of the same name. Nothing
+    // is added if any of them uses an unregistered component.
+    pub fn load_str(&mut self, json: &str) -> Result<(), PrefabError> {
+        let prefabs: HashMap<String, Map<String, Value>> = serde_json::from_str(json)?;
+        for components in prefabs.values() {
+            self.check_components(components)?;
+        }
+        self.prefabs.extend(prefabs);
+        Ok(())
+    }
+    pub fn load_file<P: AsRef<std::path::Path>>(&mut self, path: P) -> Result<(), PrefabError> {
+        self.load_str(&std::fs::read_to_string(path)?)
+    }
+    // Spawns an entity from the prefab. The returned builder can add or
+    // override components, e.g. the starting position.
+    pub fn spawn<'w>(
+        &self,
+        world: &'w mut World,
+        name: &str,
+    ) -> Result<EntityBuilder<'w>, PrefabError> {
+        self.spawn_with_overrides(world, name, &Map::new())
+    }
+    // Like `spawn`, with `overrides` merged over the prefab data first. Objects
+    // merge field by field, so `{ "CharacterView": { "radius": 20 } }` keeps
+    // the rest of the prefab's view, and `{ "MoveTarget": null }` leaves the
+    // component out.
+    pub fn spawn_with_overrides<'w>(
+        &self,
+        world: &'w mut World,
+        name: &str,
+        overrides: &Map<String, Value>,
+    ) -> Result<EntityBuilder<'w>, PrefabError> {
+        let mut components = match self.prefabs.get(name) {
+            Some(components) => components.clone(),
+            None => return Err(PrefabError::UnknownPrefab(name.to_string())),
+        };
+        self.check_components(overrides)?;
+        for (component, value) in overrides {
+            if value.is_null() {
+                components.remove(component);
+                continue;
+            }
+            match components.get_mut(component) {
+                Some(base) => merge(base, value),
+                None => {
+                    components.insert(component.clone(), value.clone());
+                }
+            }
+        }
+
+        let entity_id = world.spawn().with(PrefabName(name.to_string())).id();
+        for (component, value) in &components {
+            if let Err(err) = (self.components[component].load)(value, world, entity_id) {
+                world.despawn(entity_id);
+                return Err(err);
+            }
+        }
+        Ok(EntityBuilder::from_id(world, entity_id))
+    }
+    // The overrides that reproduce `entity_id` from its prefab: every saveable
+    // component that differs from the prefab data, reduced to the fields that
+    // differ, and `null` for every one the entity no longer has.
+    pub fn overrides_of(
+        &self,
+        world: &World,
+        entity_id: EntityID,
+        name: &str,
+    ) -> Result<Map<String, Value>, PrefabError> {
+        let prefab = match self.prefabs.get(name) {
+            Some(prefab) => prefab,
+            None => return Err(PrefabError::UnknownPrefab(name.to_string())),
+        };
+        let mut overrides = Map::new();
+        for (component, entry) in &self.components {
+            if let Some(save) = &entry.save {
+                if let Some(value) = save(world, entity_id, prefab.get(component))? {
+                    overrides.insert(component.clone(), value);
+                }
+            }
+        }
+        Ok(overrides)
+    }
+    fn check_components(&self, components: &Map<String, Value>) -> Result<(), PrefabError> {
+        for component in components.keys() {
+            if !self.components.contains_key(component) {
+                return Err(PrefabError::UnknownComponent(component.clone()));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn merge(base: &mut Value, value: &Value) {
+    match (base, value) {
+        (Value::Object(base), Value::Object(value)) => {
+            for (key, value) in value {
+                match base.get_mut(key) {
+                    Some(base) => merge(base, value),
+                    None => {
+                        base.insert(key.clone(), value.clone());
+                    }
+                }
+            }
+        }
+        (base, value) => *base = value.clone(),
+    }
+}
+
+// The smallest value that `merge` turns `base` into `value` with.
+fn diff(base: &Value, value: &Value) -> Option<Value> {
+    if base == value {
+        return None;
+    }
+    match (base, value) {
+        (Value::Object(base), Value::Object(value)) => {
+            let mut changed = Map::new();
+            for (key, value) in value {
+                let field = match base.get(key) {
+                    Some(base) => diff(base, value),
+                    None => Some(value.clone()),
+                };
+                if let Some(field) = field {
+                    changed.insert(key.clone(), field);
+                }
+            }
+            Some(Value::Object(changed))
+        }
+        _ => Some(value.clone()),
+    }
+}
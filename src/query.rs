@@ -0,0 +1,482 @@
+use crate::components::*;
+use crate::entities::*;
+#[cfg(all(feature = "parallel", not(target_arch = "wasm32")))]
+use rayon::prelude::*;
+use std::marker::PhantomData;
+
+// Joins a tuple of containers on entity id:
+//
+//     (&mut velocities, &inputs, Optional(&teams), Without(&move_targets))
+//         .query()
+//         .for_each(|(entity_id, velocity, input, team, _)| { .. });
+//
+// `&CContainer<T>` yields `&T`, `&mut CContainer<T>` yields `&mut T`,
+// `Optional` yields `Option<&T>` and `Without` yields `()` while skipping
+// entities that have the component. `Added` and `Changed` yield `()` for
+// entities whose component was added or changed during the previous tick.
+//
+// Containers that are columns of the same archetype (see `Archetype`) keep
+// the entities having all of its types in the same rows, so joining them reads
+// those rows in step without looking entities up.
+//
+// With the `parallel` feature, `.query().par_iter()` splits the remaining
+// rows of the driver across the rayon thread pool. It is left out on wasm32,
+// where rayon cannot start threads.
+pub(crate) trait Query<'a> {
+    type Fetch: Fetch<'a>;
+    fn query(self) -> QueryIter<'a, Self::Fetch>;
+}
+
+pub(crate) trait QueryParam<'a> {
+    type Fetch: Fetch<'a>;
+    fn into_fetch(self) -> Self::Fetch;
+}
+
+pub(crate) trait Fetch<'a> {
+    type Item;
+    // Number of entities this fetch can drive the iteration with, or `None`
+    // when it only filters or is optional.
+    fn len(&self) -> Option<usize>;
+    fn entity_at(&self, index: usize) -> EntityID;
+    // Identifies the row order items are stored in. A fetch sharing the
+    // layout of the driver tries the driver's row first, and only looks the
+    // entity up if another entity is stored there.
+    fn layout(&self) -> Layout;
+    // Whether `fetch` would return an item for the entity. Checked for every
+    // part of a join before any of them fetches, so a `&mut` is only handed
+    // out (and its change tick stamped) for entities the whole query accepts.
+    fn matches(&self, entity_id: EntityID, row: &Row) -> bool;
+    // Safety: callers must not fetch the same entity twice while an item
+    // returned for it is still alive, since items may be `&mut`.
+    unsafe fn fetch(&self, entity_id: EntityID, row: &Row) -> Option<Self::Item>;
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum Layout {
+    // Filters and optional fetches, which never drive.
+    None,
+    // A container on its own, by address.
+    Container(usize),
+    // A column of the archetype with this id.
+    Table(u32),
+}
+
+pub(crate) struct Row {
+    layout: Layout,
+    index: usize,
+}
+
+fn container_layout<T>(container: &CContainer<T>) -> Layout {
+    match container.table() {
+        Some(table) => Layout::Table(table),
+        None => Layout::Container(container as *const CContainer<T> as usize),
+    }
+}
+
+// Where `container` stores `entity_id`, trying the driver's row first.
+fn row_in<T>(container: &CContainer<T>, entity_id: EntityID, row: &Row) -> Option<usize> {
+    if row.layout == container_layout(container)
+        && row.index < container.len()
+        && container.entity_at(row.index) == entity_id
+    {
+        return Some(row.index);
+    }
+    container.index_of(entity_id)
+}
+
+// Only drawing needs it so far, so it is left out of headless builds.
+#[cfg(feature = "window")]
+pub(crate) struct Optional<'a, T>(pub &'a CContainer<T>);
+
+pub(crate) struct Without<'a, T>(pub &'a CContainer<T>);
+
+pub(crate) struct Added<'a, T>(pub &'a CContainer<T>);
+
+pub(crate) struct Changed<'a, T>(pub &'a CContainer<T>);
+
+pub(crate) struct Read<'a, T> {
+    container: &'a CContainer<T>,
+}
+
+impl<'a, T: 'a> QueryParam<'a> for &'a CContainer<T> {
+    type Fetch = Read<'a, T>;
+    fn into_fetch(self) -> Self::Fetch {
+        Read { container: self }
+    }
+}
+
+impl<'a, T: 'a> Fetch<'a> for Read<'a, T> {
+    type Item = &'a T;
+    fn len(&self) -> Option<usize> {
+        Some(self.container.len())
+    }
+    fn entity_at(&self, index: usize) -> EntityID {
+        self.container.entity_at(index)
+    }
+    fn layout(&self) -> Layout {
+        container_layout(self.container)
+    }
+    fn matches(&self, entity_id: EntityID, row: &Row) -> bool {
+        row_in(self.container, entity_id, row).is_some()
+    }
+    unsafe fn fetch(&self, entity_id: EntityID, row: &Row) -> Option<Self::Item> {
+        let index = row_in(self.container, entity_id, row)?;
+        Some(self.container.item_at(index))
+    }
+}
+
+pub(crate) struct Write<'a, T> {
+    map: &'a EntityIndex,
+    data: *mut Component<T>,
+    len: usize,
+    tick: u32,
+    layout: Layout,
+    phantom: PhantomData<&'a mut [Component<T>]>,
+}
+
+// SAFETY: a `Write` holds the `&mut` borrow of its container's components and
+// a shared borrow of its index, so moving it to another thread moves those
+// borrows with it.
+unsafe impl<'a, T: Send + Sync> Send for Write<'a, T> {}
+// SAFETY: `par_iter` shares one `Write` between threads. Through `&Write` they
+// only read the index and the entity ids, through raw pointers to those
+// fields, and make a `&mut T` to the component of the entity being fetched.
+// Each driver row is visited by one thread, an entity sits in one row of
+// every container, and every row holds a different entity, so no two threads
+// make a `&mut T` to the same component or write the same change tick.
+unsafe impl<'a, T: Send + Sync> Sync for Write<'a, T> {}
+
+impl<'a, T: 'a> QueryParam<'a> for &'a mut CContainer<T> {
+    type Fetch = Write<'a, T>;
+    fn into_fetch(self) -> Self::Fetch {
+        let layout = container_layout(self);
+        let tick = self.tick();
+        let (map, components) = self.split_mut();
+        Write {
+            map: map,
+            len: components.len(),
+            data: components.as_mut_ptr(),
+            tick: tick,
+            layout: layout,
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<'a, T> Write<'a, T> {
+    // Like `row_in`, without making a reference to the container, which a
+    // `&mut T` fetched earlier may point into.
+    unsafe fn row_of(&self, entity_id: EntityID, row: &Row) -> Option<usize> {
+        if row.layout == self.layout
+            && row.index < self.len
+            && Component::entity_id_of(self.data.add(row.index)) == entity_id
+        {
+            return Some(row.index);
+        }
+        let index = self.map.get(entity_id.index())?;
+        if Component::entity_id_of(self.data.add(index)) == entity_id {
+            Some(index)
+        } else {
+            None
+        }
+    }
+}
+
+impl<'a, T: 'a> Fetch<'a> for Write<'a, T> {
+    type Item = &'a mut T;
+    fn len(&self) -> Option<usize> {
+        Some(self.len)
+    }
+    fn entity_at(&self, index: usize) -> EntityID {
+        assert!(index < self.len);
+        unsafe { Component::entity_id_of(self.data.add(index)) }
+    }
+    fn layout(&self) -> Layout {
+        self.layout
+    }
+    fn matches(&self, entity_id: EntityID, row: &Row) -> bool {
+        unsafe { self.row_of(entity_id, row).is_some() }
+    }
+    unsafe fn fetch(&self, entity_id: EntityID, row: &Row) -> Option<Self::Item> {
+        let index = self.row_of(entity_id, row)?;
+        Some(Component::inner_mut_of(self.data.add(index), self.tick))
+    }
+}
+
+#[cfg(feature = "window")]
+pub(crate) struct OptionalFetch<'a, T> {
+    container: &'a CContainer<T>,
+}
+
+#[cfg(feature = "window")]
+impl<'a, T: 'a> QueryParam<'a> for Optional<'a, T> {
+    type Fetch = OptionalFetch<'a, T>;
+    fn into_fetch(self) -> Self::Fetch {
+        OptionalFetch { container: self.0 }
+    }
+}
+
+#[cfg(feature = "window")]
+impl<'a, T: 'a> Fetch<'a> for OptionalFetch<'a, T> {
+    type Item = Option<&'a T>;
+    fn len(&self) -> Option<usize> {
+        None
+    }
+    fn entity_at(&self, _: usize) -> EntityID {
+        unreachable!()
+    }
+    fn layout(&self) -> Layout {
+        Layout::None
+    }
+    fn matches(&self, _: EntityID, _: &Row) -> bool {
+        true
+    }
+    unsafe fn fetch(&self, entity_id: EntityID, _: &Row) -> Option<Self::Item> {
+        Some(self.container.get(entity_id))
+    }
+}
+
+pub(crate) struct WithoutFetch<'a, T> {
+    container: &'a CContainer<T>,
+}
+
+impl<'a, T: 'a> QueryParam<'a> for Without<'a, T> {
+    type Fetch = WithoutFetch<'a, T>;
+    fn into_fetch(self) -> Self::Fetch {
+        WithoutFetch { container: self.0 }
+    }
+}
+
+impl<'a, T: 'a> Fetch<'a> for WithoutFetch<'a, T> {
+    type Item = ();
+    fn len(&self) -> Option<usize> {
+        None
+    }
+    fn entity_at(&self, _: usize) -> EntityID {
+        unreachable!()
+    }
+    fn layout(&self) -> Layout {
+        Layout::None
+    }
+    fn matches(&self, entity_id: EntityID, _: &Row) -> bool {
+        !self.container.contains(entity_id)
+    }
+    unsafe fn fetch(&self, entity_id: EntityID, row: &Row) -> Option<Self::Item> {
+        if self.matches(entity_id, row) {
+            Some(())
+        } else {
+            None
+        }
+    }
+}
+
+pub(crate) struct AddedFetch<'a, T> {
+    container: &'a CContainer<T>,
+}
+
+impl<'a, T: 'a> QueryParam<'a> for Added<'a, T> {
+    type Fetch = AddedFetch<'a, T>;
+    fn into_fetch(self) -> Self::Fetch {
+        AddedFetch { container: self.0 }
+    }
+}
+
+impl<'a, T: 'a> Fetch<'a> for AddedFetch<'a, T> {
+    type Item = ();
+    fn len(&self) -> Option<usize> {
+        None
+    }
+    fn entity_at(&self, _: usize) -> EntityID {
+        unreachable!()
+    }
+    fn layout(&self) -> Layout {
+        Layout::None
+    }
+    fn matches(&self, entity_id: EntityID, _: &Row) -> bool {
+        self.container.is_added(entity_id)
+    }
+    unsafe fn fetch(&self, entity_id: EntityID, row: &Row) -> Option<Self::Item> {
+        if self.matches(entity_id, row) {
+            Some(())
+        } else {
+            None
+        }
+    }
+}
+
+pub(crate) struct ChangedFetch<'a, T> {
+    container: &'a CContainer<T>,
+}
+
+impl<'a, T: 'a> QueryParam<'a> for Changed<'a, T> {
+    type Fetch = ChangedFetch<'a, T>;
+    fn into_fetch(self) -> Self::Fetch {
+        ChangedFetch { container: self.0 }
+    }
+}
+
+impl<'a, T: 'a> Fetch<'a> for ChangedFetch<'a, T> {
+    type Item = ();
+    fn len(&self) -> Option<usize> {
+        None
+    }
+    fn entity_at(&self, _: usize) -> EntityID {
+        unreachable!()
+    }
+    fn layout(&self) -> Layout {
+        Layout::None
+    }
+    fn matches(&self, entity_id: EntityID, _: &Row) -> bool {
+        self.container.is_changed(entity_id)
+    }
+    unsafe fn fetch(&self, entity_id: EntityID, row: &Row) -> Option<Self::Item> {
+        if self.matches(entity_id, row) {
+            Some(())
+        } else {
+            None
+        }
+    }
+}
+
+pub(crate) struct Join<F> {
+    fetches: F,
+    driver: usize,
+}
+
+pub(crate) struct QueryIter<'a, F> {
+    fetch: F,
+    index: usize,
+    len: usize,
+    phantom: PhantomData<&'a ()>,
+}
+
+impl<'a, F: Fetch<'a>> QueryIter<'a, F> {
+    fn new(fetch: F) -> Self {
+        let len = fetch.len().unwrap_or(0);
+        Self {
+            fetch: fetch,
+            index: 0,
+            len: len,
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<'a, F: Fetch<'a>> Iterator for QueryIter<'a, F> {
+    type Item = F::Item;
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.index < self.len {
+            let entity_id = self.fetch.entity_at(self.index);
+            let row = Row {
+                layout: self.fetch.layout(),
+                index: self.index,
+            };
+            self.index += 1;
+            // The driver holds each entity once, so every entity is fetched
+            // at most once per iteration. This holds whichever container
+            // drives, so a `&mut` container does not have to be the driver.
+            if let Some(item) = unsafe { self.fetch.fetch(entity_id, &row) } {
+                return Some(item);
+            }
+        }
+        None
+    }
+}
+
+#[cfg(all(feature = "parallel", not(target_arch = "wasm32")))]
+impl<'a, F: Fetch<'a> + Send + Sync> QueryIter<'a, F>
+where
+    F::Item: Send,
+{
+    pub fn par_iter(self) -> impl ParallelIterator<Item = F::Item> + 'a
+    where
+        F: 'a,
+    {
+        let fetch = self.fetch;
+        (self.index..self.len)
+            .into_par_iter()
+            .filter_map(move |index| {
+                let row = Row {
+                    layout: fetch.layout(),
+                    index: index,
+                };
+                // As in `next`, the driver visits each entity once.
+                unsafe { fetch.fetch(fetch.entity_at(index), &row) }
+            })
+    }
+}
+
+macro_rules! impl_query {
+    ($($index:tt $param:ident $var:ident),+) => {
+        impl<'a, $($param: QueryParam<'a>),+> Query<'a> for ($($param,)+) {
+            type Fetch = Join<($($param::Fetch,)+)>;
+            fn query(self) -> QueryIter<'a, Self::Fetch> {
+                let ($($var,)+) = self;
+                let fetches = ($($var.into_fetch(),)+);
+                // Drive from the smallest required container so that a
+                // join against a rare component only visits its entities.
+                let lens = [$(fetches.$index.len()),+];
+                let driver = lens
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(index, len)| Some((index, (*len)?)))
+                    .min_by_key(|(_, len)| *len)
+                    .map(|(index, _)| index)
+                    .expect("query needs at least one required component");
+                QueryIter::new(Join {
+                    fetches: fetches,
+                    driver: driver,
+                })
+            }
+        }
+
+        impl<'a, $($param: Fetch<'a>),+> Fetch<'a> for Join<($($param,)+)> {
+            type Item = (EntityID, $($param::Item,)+);
+            fn len(&self) -> Option<usize> {
+                $(
+                    if self.driver == $index {
+                        return self.fetches.$index.len();
+                    }
+                )+
+                unreachable!()
+            }
+            fn entity_at(&self, index: usize) -> EntityID {
+                $(
+                    if self.driver == $index {
+                        return self.fetches.$index.entity_at(index);
+                    }
+                )+
+                unreachable!()
+            }
+            fn layout(&self) -> Layout {
+                $(
+                    if self.driver == $index {
+                        return self.fetches.$index.layout();
+                    }
+                )+
+                unreachable!()
+            }
+            fn matches(&self, entity_id: EntityID, row: &Row) -> bool {
+                true $(&& self.fetches.$index.matches(entity_id, row))+
+            }
+            unsafe fn fetch(&self, entity_id: EntityID, row: &Row) -> Option<Self::Item> {
+                if !self.matches(entity_id, row) {
+                    return None;
+                }
+                $(
+                    let $var = self.fetches.$index.fetch(entity_id, row)?;
+                )+
+                Some((entity_id, $($var,)+))
+            }
+        }
+    };
+}
+
+impl_query!(0 A a);
+impl_query!(0 A a, 1 B b);
+impl_query!(0 A a, 1 B b, 2 C c);
+impl_query!(0 A a, 1 B b, 2 C c, 3 D d);
+impl_query!(0 A a, 1 B b, 2 C c, 3 D d, 4 E e);
+impl_query!(0 A a, 1 B b, 2 C c, 3 D d, 4 E e, 5 F f);
+impl_query!(0 A a, 1 B b, 2 C c, 3 D d, 4 E e, 5 F f, 6 G g);
+impl_query!(0 A a, 1 B b, 2 C c, 3 D d, 4 E e, 5 F f, 6 G g, 7 H h);
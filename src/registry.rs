@@ -0,0 +1,238 @@
+use crate::components::*;
+use crate::entities::*;
+use crate::world::*;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+use std::any::Any;
+use std::collections::*;
+use std::fmt;
+
+#[derive(Debug)]
+pub(crate) enum RegistryError {
+    Json(serde_json::Error),
+    UnknownComponent(String),
+    UnknownResource(String),
+}
+
+impl fmt::Display for RegistryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RegistryError::Json(err) => write!(f, "invalid json: {}", err),
+            RegistryError::UnknownComponent(name) => write!(f, "unknown component `{}`", name),
+            RegistryError::UnknownResource(name) => write!(f, "unknown resource `{}`", name),
+        }
+    }
+}
+
+impl std::error::Error for RegistryError {}
+
+impl From<serde_json::Error> for RegistryError {
+    fn from(err: serde_json::Error) -> Self {
+        RegistryError::Json(err)
+    }
+}
+
+pub(crate) type ComponentRows = Vec<(EntityID, Value)>;
+
+type SaveComponents = Box<dyn Fn(&World) -> Result<ComponentRows, RegistryError>>;
+type LoadComponents = Box<dyn Fn(&mut World, &ComponentRows) -> Result<(), RegistryError>>;
+type RestoreComponents = Box<dyn Fn(&mut World, &dyn Any)>;
+type SaveResource = Box<dyn Fn(&World) -> Result<Option<Value>, RegistryError>>;
+type LoadResource = Box<dyn Fn(&mut World, Option<&Value>) -> Result<(), RegistryError>>;
+type RestoreResource = Box<dyn Fn(&mut World, Option<&dyn Any>)>;
+// A copy of a storage or resource, or `None` if the world has none.
+type TakeSnapshot = Box<dyn Fn(&World) -> Option<Box<dyn Any>>>;
+
+struct ComponentRegistration {
+    save: SaveComponents,
+    load: LoadComponents,
+    snapshot: TakeSnapshot,
+    restore: RestoreComponents,
+}
+
+struct ResourceRegistration {
+    save: SaveResource,
+    load: LoadResource,
+    snapshot: TakeSnapshot,
+    restore: RestoreResource,
+}
+
+// Copies of registered storages and resources, in registry order, for
+// `World::snapshot`.
+pub(crate) struct SnapshotData {
+    components: Vec<Option<Box<dyn Any>>>,
+    resources: Vec<Option<Box<dyn Any>>>,
+}
+
+// Stable names for the component and resource types that take part in
+// `World::serialize` and `World::snapshot`. Types that are not registered are
+// neither saved nor restored, so anything that must survive a save has to be
+// listed here.
+#[derive(Default)]
+pub(crate) struct Registry {
+    components: BTreeMap<String, ComponentRegistration>,
+    resources: BTreeMap<String, ResourceRegistration>,
+}
+
+impl Registry {
+    pub fn register_component<T>(&mut self, name: &str)
+    where
+        T: Clone + Serialize + DeserializeOwned + Send + Sync + 'static,
+    {
+        let registration = ComponentRegistration {
+            save: Box::new(|world| {
+                if !world.is_registered::<T>() {
+                    return Ok(Vec::new());
+                }
+                let mut rows = Vec::new();
+                for (entity_id, component) in world.storage::<T>().iter() {
+                    rows.push((entity_id, serde_json::to_value(component)?));
+                }
+                Ok(rows)
+            }),
+            load: Box::new(|world, rows| {
+                world.register::<T>();
+                let mut storage = world.storage_mut::<T>();
+                for (entity_id, value) in rows {
+                    storage.push(*entity_id, T::deserialize(value)?);
+                }
+                Ok(())
+            }),
+            snapshot: Box::new(|world| {
+                if !world.is_registered::<T>() {
+                    return None;
+                }
+                Some(Box::new(world.storage::<T>().clone()))
+            }),
+            restore: Box::new(|world, storage| {
+                let storage = storage.downcast_ref::<CContainer<T>>().unwrap();
+                world.set_storage(storage.clone());
+            }),
+        };
+        self.components.insert(name.to_string(), registration);
+    }
+    pub fn register_resource<R>(&mut self, name: &str)
+    where
+        R: Clone + Serialize + DeserializeOwned + Send + Sync + 'static,
+    {
+        let registration = ResourceRegistration {
+            save: Box::new(|world| {
+                if !world.contains_resource::<R>() {
+                    return Ok(None);
+                }
+                Ok(Some(serde_json::to_value(&*world.resource::<R>())?))
+            }),
+            load: Box::new(|world, value| {
+                match value {
+                    Some(value) => world.insert_resource(R::deserialize(value)?),
+                    None => {
+                        world.remove_resource::<R>();
+                    }
+                }
+                Ok(())
+            }),
+            snapshot: Box::new(|world| {
+                if !world.contains_resource::<R>() {
+                    return None;
+                }
+                Some(Box::new(world.resource::<R>().clone()))
+            }),
+            restore: Box::new(|world, resource| match resource {
+                Some(resource) => {
+                    world.insert_resource(resource.downcast_ref::<R>().unwrap().clone())
+                }
+                None => {
+                    world.remove_resource::<R>();
+                }
+            }),
+        };
+        self.resources.insert(name.to_string(), registration);
+    }
+    pub fn save_components(
+        &self,
+        world: &World,
+    ) -> Result<BTreeMap<String, ComponentRows>, RegistryError> {
+        let mut components = BTreeMap::new();
+        for (name, registration) in &self.components {
+            let rows = (registration.save)(world)?;
+            if !rows.is_empty() {
+                components.insert(name.clone(), rows);
+            }
+        }
+        Ok(components)
+    }
+    pub fn save_resources(&self, world: &World) -> Result<BTreeMap<String, Value>, RegistryError> {
+        let mut resources = BTreeMap::new();
+        for (name, registration) in &self.resources {
+            if let Some(value) = (registration.save)(world)? {
+                resources.insert(name.clone(), value);
+            }
+        }
+        Ok(resources)
+    }
+    // Fails before touching the world if `data` names an unregistered type.
+    pub fn check(&self, data: &WorldData) -> Result<(), RegistryError> {
+        for name in data.components.keys() {
+            if !self.components.contains_key(name) {
+                return Err(RegistryError::UnknownComponent(name.clone()));
+            }
+        }
+        for name in data.resources.keys() {
+            if !self.resources.contains_key(name) {
+                return Err(RegistryError::UnknownResource(name.clone()));
+            }
+        }
+        Ok(())
+    }
+    pub fn load_components(
+        &self,
+        world: &mut World,
+        components: &BTreeMap<String, ComponentRows>,
+    ) -> Result<(), RegistryError> {
+        for (name, rows) in components {
+            (self.components[name].load)(world, rows)?;
+        }
+        Ok(())
+    }
+    pub fn load_resources(
+        &self,
+        world: &mut World,
+        resources: &BTreeMap<String, Value>,
+    ) -> Result<(), RegistryError> {
+        for (name, registration) in &self.resources {
+            (registration.load)(world, resources.get(name))?;
+        }
+        Ok(())
+    }
+    pub fn snapshot(&self, world: &World) -> SnapshotData {
+        SnapshotData {
+            components: self
+                .components
+                .values()
+                .map(|registration| (registration.snapshot)(world))
+                .collect(),
+            resources: self
+                .resources
+                .values()
+                .map(|registration| (registration.snapshot)(world))
+                .collect(),
+        }
+    }
+    // `data` must come from `snapshot` on this same registry.
+    pub fn restore(&self, world: &mut World, data: &SnapshotData) {
+        assert!(
+            data.components.len() == self.components.len()
+                && data.resources.len() == self.resources.len(),
+            "snapshot was taken with a different registry"
+        );
+        for (registration, storage) in self.components.values().zip(&data.components) {
+            if let Some(storage) = storage {
+                (registration.restore)(world, storage.as_ref());
+            }
+        }
+        for (registration, resource) in self.resources.values().zip(&data.resources) {
+            (registration.restore)(world, resource.as_ref().map(|resource| resource.as_ref()));
+        }
+    }
+}
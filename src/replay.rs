@@ -0,0 +1,110 @@
+use crate::components::*;
+use crate::entities::*;
+use crate::world::*;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fmt;
+use std::path::Path;
+
+pub(crate) type TickInputs = Vec<(EntityID, Input)>;
+
+#[derive(Debug)]
+pub enum ReplayError {
+    Diverged { expected: u64, actual: u64 },
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReplayError::Diverged { expected, actual } => write!(
+                f,
+                "replay diverged: expected state hash {:016x}, got {:016x}",
+                expected, actual
+            ),
+        }
+    }
+}
+
+impl Error for ReplayError {}
+
+// The state a recording started from, the `Input` of every controlled entity
+// at each step after that, and the hash of the state it ended in. Replaying
+// the inputs from the same start has to end in the same state, so a recording
+// of a bug reproduces it exactly.
+#[derive(Serialize, Deserialize)]
+pub struct Recording {
+    start: WorldData,
+    inputs: Vec<TickInputs>,
+    hash: u64,
+}
+
+impl Recording {
+    pub fn ticks(&self) -> usize {
+        self.inputs.len()
+    }
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Box<dyn Error>> {
+        std::fs::write(path, serde_json::to_string(self)?)?;
+        Ok(())
+    }
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error>> {
+        Ok(serde_json::from_str(&std::fs::read_to_string(path)?)?)
+    }
+}
+
+pub(crate) struct Recorder {
+    start: WorldData,
+    inputs: Vec<TickInputs>,
+}
+
+impl Recorder {
+    pub fn new(start: WorldData) -> Self {
+        Self {
+            start: start,
+            inputs: Vec::new(),
+        }
+    }
+    // Called after every step, when the `Input` components still hold what
+    // the step ran with.
+    pub fn record(&mut self, inputs: &CContainer<Input>) {
+        self.inputs
+            .push(inputs.iter().map(|(id, input)| (id, *input)).collect());
+    }
+    pub fn finish(self, hash: u64) -> Recording {
+        Recording {
+            start: self.start,
+            inputs: self.inputs,
+            hash: hash,
+        }
+    }
+}
+
+pub(crate) struct Replay {
+    inputs: std::vec::IntoIter<TickInputs>,
+    hash: u64,
+}
+
+impl Replay {
+    // Splits `recording` into the state to load and the inputs to play.
+    pub fn new(recording: Recording) -> (WorldData, Self) {
+        let replay = Self {
+            inputs: recording.inputs.into_iter(),
+            hash: recording.hash,
+        };
+        (recording.start, replay)
+    }
+    pub fn next(&mut self) -> Option<TickInputs> {
+        self.inputs.next()
+    }
+    pub fn is_finished(&self) -> bool {
+        self.inputs.len() == 0
+    }
+    pub fn check(&self, hash: u64) -> Result<(), ReplayError> {
+        if hash != self.hash {
+            return Err(ReplayError::Diverged {
+                expected: self.hash,
+                actual: hash,
+            });
+        }
+        Ok(())
+    }
+}
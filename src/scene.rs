@@ -0,0 +1,76 @@
+use crate::entities::*;
+use crate::prefab::*;
+use crate::world::*;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::path::Path;
+
+// A level layout: the prefab instances to spawn, in order, each with the
+// component data that differs from its prefab.
+//
+//     { "entities": [
+//         { "prefab": "enemy", "overrides": { "Position": { "x": 20, "y": 20 } } }
+//     ] }
+#[derive(Default, Serialize, Deserialize)]
+pub(crate) struct Scene {
+    pub entities: Vec<SceneEntity>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub(crate) struct SceneEntity {
+    pub prefab: String,
+    #[serde(default, skip_serializing_if = "Map::is_empty")]
+    pub overrides: Map<String, Value>,
+}
+
+impl Scene {
+    pub fn parse(json: &str) -> Result<Self, PrefabError> {
+        Ok(serde_json::from_str(json)?)
+    }
+    pub fn to_json(&self) -> Result<String, PrefabError> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+    // Collects every entity that was spawned from a prefab. Entities created
+    // in code are not part of the scene.
+    pub fn from_world(world: &World, prefabs: &Prefabs) -> Result<Self, PrefabError> {
+        let mut entities = Vec::new();
+        if world.is_registered::<PrefabName>() {
+            for (entity_id, name) in world.storage::<PrefabName>().iter() {
+                entities.push(SceneEntity {
+                    prefab: name.0.clone(),
+                    overrides: prefabs.overrides_of(world, entity_id, &name.0)?,
+                });
+            }
+        }
+        Ok(Self { entities: entities })
+    }
+    pub fn spawn(
+        &self,
+        world: &mut World,
+        prefabs: &Prefabs,
+    ) -> Result<Vec<EntityID>, PrefabError> {
+        let mut spawned = Vec::new();
+        for entity in &self.entities {
+            let builder = prefabs.spawn_with_overrides(world, &entity.prefab, &entity.overrides)?;
+            spawned.push(builder.id());
+        }
+        Ok(spawned)
+    }
+}
+
+pub(crate) fn load_scene<P: AsRef<Path>>(
+    world: &mut World,
+    prefabs: &Prefabs,
+    path: P,
+) -> Result<Vec<EntityID>, PrefabError> {
+    Scene::parse(&std::fs::read_to_string(path)?)?.spawn(world, prefabs)
+}
+
+pub(crate) fn save_scene<P: AsRef<Path>>(
+    world: &World,
+    prefabs: &Prefabs,
+    path: P,
+) -> Result<(), PrefabError> {
+    std::fs::write(path, Scene::from_world(world, prefabs)?.to_json()?)?;
+    Ok(())
+}
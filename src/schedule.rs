@@ -0,0 +1,835 @@
+use crate::cell::*;
+use crate::commands::*;
+use crate::components::*;
+use crate::entities::*;
+use crate::events::*;
+use crate::systems::*;
+use crate::time::*;
+use crate::world::*;
+use std::any::{type_name, TypeId};
+use std::collections::*;
+use std::fmt;
+use std::marker::PhantomData;
+use std::ops::Deref;
+
+// Types stored as world resources rather than component containers. A
+// system's `Update`/`Refer` types are fetched from the world by shape: a
+// `CContainer<T>` is a storage, anything marked here is a resource.
+pub(crate) trait Resource: 'static {}
+
+impl Resource for Commands {}
+impl Resource for InputState {}
+impl Resource for Time {}
+impl<E: 'static> Resource for Events<E> {}
+impl<E: 'static, S: 'static> Resource for EventReader<E, S> {}
+
+// The storages and resources a system borrows, keyed by the type of the
+// storage or resource itself.
+#[derive(Default)]
+pub(crate) struct Access {
+    reads: Vec<(TypeId, String)>,
+    writes: Vec<(TypeId, String)>,
+}
+
+impl Access {
+    pub fn add_read<T: 'static>(&mut self) {
+        self.reads.push((TypeId::of::<T>(), short_type_name::<T>()));
+    }
+    pub fn add_write<T: 'static>(&mut self) {
+        self.writes
+            .push((TypeId::of::<T>(), short_type_name::<T>()));
+    }
+    pub fn reads(&self, type_id: TypeId) -> bool {
+        self.reads.iter().any(|(read, _)| *read == type_id)
+    }
+    pub fn writes(&self, type_id: TypeId) -> bool {
+        self.writes.iter().any(|(write, _)| *write == type_id)
+    }
+    // A type borrowed twice by the same system, which would panic when the
+    // system runs.
+    fn overlap(&self) -> Option<&str> {
+        for (i, (type_id, name)) in self.writes.iter().enumerate() {
+            if self.writes[i + 1..]
+                .iter()
+                .any(|(write, _)| write == type_id)
+                || self.reads(*type_id)
+            {
+                return Some(name);
+            }
+        }
+        None
+    }
+    // Every type that `self` and `other` both borrow, where at least one of
+    // them borrows it mutably.
+    fn conflicts_with(&self, other: &Access) -> Vec<(&str, ConflictKind)> {
+        let mut conflicts = Vec::new();
+        for (type_id, name) in &self.writes {
+            if other.writes(*type_id) {
+                conflicts.push((name.as_str(), ConflictKind::WriteWrite));
+            } else if other.reads(*type_id) {
+                conflicts.push((name.as_str(), ConflictKind::ReadWrite));
+            }
+        }
+        for (type_id, name) in &self.reads {
+            if other.writes(*type_id) {
+                conflicts.push((name.as_str(), ConflictKind::ReadWrite));
+            }
+        }
+        conflicts
+    }
+}
+
+// `type_name` without module paths, e.g. `ComponentContainer<Position>`.
+fn short_type_name<T: ?Sized>() -> String {
+    let name = type_name::<T>();
+    let mut short = String::new();
+    let mut segment_start = 0;
+    for (i, c) in name.char_indices() {
+        if c == ':' {
+            segment_start = i + 1;
+        } else if !(c.is_alphanumeric() || c == '_') {
+            short.push_str(&name[segment_start..=i]);
+            segment_start = i + 1;
+        }
+    }
+    short.push_str(&name[segment_start..]);
+    short
+}
+
+// A system's `Update` type: a container or resource, or a tuple of `&mut`
+// to them.
+pub(crate) trait UpdateData {
+    type Item<'w>;
+    fn with_mut<F: FnOnce(&mut Self::Item<'_>)>(world: &World, f: F);
+    fn access(access: &mut Access);
+}
+
+// A system's `Refer` type: a container or resource, `()`, or a tuple of `&`
+// to them.
+pub(crate) trait ReferData {
+    type Item<'w>;
+    fn with_ref<F: FnOnce(&Self::Item<'_>)>(world: &World, f: F);
+    fn access(access: &mut Access);
+}
+
+// One `&mut` element of an `Update` tuple.
+pub(crate) trait WriteData {
+    type Guard<'w>;
+    type Item<'g>;
+    fn fetch(world: &World) -> Self::Guard<'_>;
+    fn item<'g>(guard: &'g mut Self::Guard<'_>) -> Self::Item<'g>;
+    fn access(access: &mut Access);
+}
+
+// One `&` element of a `Refer` tuple.
+pub(crate) trait ReadData {
+    type Guard<'w>;
+    type Item<'g>;
+    fn fetch(world: &World) -> Self::Guard<'_>;
+    fn item<'g>(guard: &'g Self::Guard<'_>) -> Self::Item<'g>;
+    fn access(access: &mut Access);
+}
+
+impl<T: 'static> UpdateData for CContainer<T> {
+    type Item<'w> = CContainer<T>;
+    fn with_mut<F: FnOnce(&mut Self::Item<'_>)>(world: &World, f: F) {
+        f(&mut *world.storage_mut::<T>())
+    }
+    fn access(access: &mut Access) {
+        access.add_write::<CContainer<T>>();
+    }
+}
+
+impl<R: Resource> UpdateData for R {
+    type Item<'w> = R;
+    fn with_mut<F: FnOnce(&mut Self::Item<'_>)>(world: &World, f: F) {
+        f(&mut *world.resource_mut::<R>())
+    }
+    fn access(access: &mut Access) {
+        access.add_write::<R>();
+    }
+}
+
+impl<T: 'static> ReferData for CContainer<T> {
+    type Item<'w> = CContainer<T>;
+    fn with_ref<F: FnOnce(&Self::Item<'_>)>(world: &World, f: F) {
+        f(&*world.storage::<T>())
+    }
+    fn access(access: &mut Access) {
+        access.add_read::<CContainer<T>>();
+    }
+}
+
+impl<R: Resource> ReferData for R {
+    type Item<'w> = R;
+    fn with_ref<F: FnOnce(&Self::Item<'_>)>(world: &World, f: F) {
+        f(&*world.resource::<R>())
+    }
+    fn access(access: &mut Access) {
+        access.add_read::<R>();
+    }
+}
+
+impl ReferData for () {
+    type Item<'w> = ();
+    fn with_ref<F: FnOnce(&Self::Item<'_>)>(_: &World, f: F) {
+        f(&())
+    }
+    fn access(_: &mut Access) {}
+}
+
+impl<T: 'static> WriteData for &mut CContainer<T> {
+    type Guard<'w> = CellRefMut<'w, CContainer<T>>;
+    type Item<'g> = &'g mut CContainer<T>;
+    fn fetch(world: &World) -> Self::Guard<'_> {
+        world.storage_mut::<T>()
+    }
+    fn item<'g>(guard: &'g mut Self::Guard<'_>) -> Self::Item<'g> {
+        &mut **guard
+    }
+    fn access(access: &mut Access) {
+        access.add_write::<CContainer<T>>();
+    }
+}
+
+impl<R: Resource> WriteData for &mut R {
+    type Guard<'w> = CellRefMut<'w, R>;
+    type Item<'g> = &'g mut R;
+    fn fetch(world: &World) -> Self::Guard<'_> {
+        world.resource_mut::<R>()
+    }
+    fn item<'g>(guard: &'g mut Self::Guard<'_>) -> Self::Item<'g> {
+        &mut **guard
+    }
+    fn access(access: &mut Access) {
+        access.add_write::<R>();
+    }
+}
+
+impl<T: 'static> ReadData for &CContainer<T> {
+    type Guard<'w> = CellRef<'w, CContainer<T>>;
+    type Item<'g> = &'g CContainer<T>;
+    fn fetch(world: &World) -> Self::Guard<'_> {
+        world.storage::<T>()
+    }
+    fn item<'g>(guard: &'g Self::Guard<'_>) -> Self::Item<'g> {
+        &**guard
+    }
+    fn access(access: &mut Access) {
+        access.add_read::<CContainer<T>>();
+    }
+}
+
+impl<R: Resource> ReadData for &R {
+    type Guard<'w> = CellRef<'w, R>;
+    type Item<'g> = &'g R;
+    fn fetch(world: &World) -> Self::Guard<'_> {
+        world.resource::<R>()
+    }
+    fn item<'g>(guard: &'g Self::Guard<'_>) -> Self::Item<'g> {
+        &**guard
+    }
+    fn access(access: &mut Access) {
+        access.add_read::<R>();
+    }
+}
+
+// The entities whose `T` was removed since the last `clear_trackers`, by
+// despawning or otherwise, for a system's `Refer`.
+pub(crate) struct RemovedComponents<'a, T> {
+    entities: &'a [EntityID],
+    phantom: PhantomData<T>,
+}
+
+impl<'a, T> Deref for RemovedComponents<'a, T> {
+    type Target = [EntityID];
+    fn deref(&self) -> &[EntityID] {
+        self.entities
+    }
+}
+
+impl<'a, T: 'static> ReadData for RemovedComponents<'a, T> {
+    type Guard<'w> = CellRef<'w, CContainer<T>>;
+    type Item<'g> = RemovedComponents<'g, T>;
+    fn fetch(world: &World) -> Self::Guard<'_> {
+        world.storage::<T>()
+    }
+    fn item<'g>(guard: &'g Self::Guard<'_>) -> Self::Item<'g> {
+        RemovedComponents {
+            entities: guard.removed(),
+            phantom: PhantomData,
+        }
+    }
+    fn access(access: &mut Access) {
+        access.add_read::<CContainer<T>>();
+    }
+}
+
+macro_rules! impl_system_data {
+    ($($data:ident $guard:ident),+) => {
+        impl<$($data: WriteData),+> UpdateData for ($($data,)+) {
+            type Item<'w> = ($($data::Item<'w>,)+);
+            fn with_mut<Op: FnOnce(&mut Self::Item<'_>)>(world: &World, f: Op) {
+                $(let mut $guard = $data::fetch(world);)+
+                let mut items = ($($data::item(&mut $guard),)+);
+                f(&mut items)
+            }
+            fn access(access: &mut Access) {
+                $($data::access(access);)+
+            }
+        }
+
+        impl<$($data: ReadData),+> ReferData for ($($data,)+) {
+            type Item<'w> = ($($data::Item<'w>,)+);
+            fn with_ref<Op: FnOnce(&Self::Item<'_>)>(world: &World, f: Op) {
+                $(let $guard = $data::fetch(world);)+
+                let items = ($($data::item(&$guard),)+);
+                f(&items)
+            }
+            fn access(access: &mut Access) {
+                $($data::access(access);)+
+            }
+        }
+    };
+}
+
+impl_system_data!(A a);
+impl_system_data!(A a, B b);
+impl_system_data!(A a, B b, C c);
+impl_system_data!(A a, B b, C c, D d);
+impl_system_data!(A a, B b, C c, D d, E e);
+impl_system_data!(A a, B b, C c, D d, E e, F f);
+impl_system_data!(A a, B b, C c, D d, E e, F f, G g);
+impl_system_data!(A a, B b, C c, D d, E e, F f, G g, H h);
+
+// `System<U, R>::process` with the associated types pinned to `U` and `R`, so
+// that it can be named under the higher-ranked bound in `system`.
+pub(crate) trait Run<U, R> {
+    fn run(update: &mut U, refer: &R);
+}
+
+impl<U, R> Run<U, R> for System<U, R>
+where
+    System<U, R>: SystemProcess + SystemInterface<Update = U, Refer = R>,
+{
+    fn run(update: &mut U, refer: &R) {
+        <System<U, R> as SystemProcess>::process(update, refer);
+    }
+}
+
+fn run_system<U: UpdateData, R: ReferData>(world: &World)
+where
+    for<'a, 'b> System<U::Item<'a>, R::Item<'b>>: Run<U::Item<'a>, R::Item<'b>>,
+{
+    U::with_mut(world, |update| {
+        R::with_ref(world, |refer| System::run(update, refer))
+    });
+}
+
+pub(crate) struct SystemDescriptor {
+    label: &'static str,
+    run: fn(&World),
+    access: Access,
+    before: Vec<&'static str>,
+    after: Vec<&'static str>,
+}
+
+impl SystemDescriptor {
+    pub fn before(mut self, label: &'static str) -> Self {
+        self.before.push(label);
+        self
+    }
+    pub fn after(mut self, label: &'static str) -> Self {
+        self.after.push(label);
+        self
+    }
+}
+
+// Describes the `System<U, R>` implementation under `label`, e.g.
+// `system::<CContainer<Position>, CContainer<Velocity>>("apply_velocity")`.
+pub(crate) fn system<U: UpdateData, R: ReferData>(label: &'static str) -> SystemDescriptor
+where
+    for<'a, 'b> System<U::Item<'a>, R::Item<'b>>: Run<U::Item<'a>, R::Item<'b>>,
+{
+    let mut access = Access::default();
+    U::access(&mut access);
+    R::access(&mut access);
+    SystemDescriptor {
+        label: label,
+        run: run_system::<U, R>,
+        access: access,
+        before: Vec::new(),
+        after: Vec::new(),
+    }
+}
+
+#[derive(Debug)]
+pub(crate) enum ScheduleError {
+    DuplicateLabel(&'static str),
+    UnknownLabel {
+        system: &'static str,
+        label: &'static str,
+    },
+    Cycle(Vec<&'static str>),
+    // The system borrows a storage or resource mutably and borrows it again.
+    AliasedBorrow {
+        system: &'static str,
+        data: String,
+    },
+}
+
+impl fmt::Display for ScheduleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ScheduleError::DuplicateLabel(label) => {
+                write!(f, "more than one system is labelled `{}`", label)
+            }
+            ScheduleError::UnknownLabel { system, label } => {
+                write!(
+                    f,
+                    "`{}` is ordered against unknown system `{}`",
+                    system, label
+                )
+            }
+            ScheduleError::Cycle(labels) => {
+                write!(f, "systems are ordered in a cycle: {}", labels.join(" -> "))
+            }
+            ScheduleError::AliasedBorrow { system, data } => {
+                write!(f, "`{}` borrows `{}` mutably more than once", system, data)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ScheduleError {}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum ConflictKind {
+    ReadWrite,
+    WriteWrite,
+}
+
+// Two systems that borrow the same storage or resource, at least one of them
+// mutably, with no `before`/`after` path between them. Their relative order
+// is only decided by the order they were added in.
+#[derive(Debug)]
+pub(crate) struct Conflict {
+    pub systems: [&'static str; 2],
+    pub data: String,
+    pub kind: ConflictKind,
+}
+
+impl fmt::Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let [first, second] = self.systems;
+        match self.kind {
+            ConflictKind::ReadWrite => write!(
+                f,
+                "`{}` and `{}` read and write `{}` with no declared order",
+                first, second, self.data
+            ),
+            ConflictKind::WriteWrite => write!(
+                f,
+                "`{}` and `{}` both write `{}` with no declared order",
+                first, second, self.data
+            ),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) enum ExecutionMode {
+    // One system at a time on the calling thread, in the sorted order.
+    SingleThreaded,
+    // Systems that share no mutably borrowed storage or resource run at the
+    // same time on the rayon thread pool. Without the `parallel` feature, and
+    // on wasm32 where rayon has no threads to use, this runs the same stages
+    // one system at a time.
+    #[default]
+    Parallel,
+}
+
+// Systems run in an order that satisfies every `before`/`after` constraint.
+// Systems with no constraint between them keep the order they were added in.
+// Both execution modes give the same results: systems only run at the same
+// time when neither of them borrows anything the other borrows mutably.
+#[derive(Default)]
+pub(crate) struct Schedule {
+    systems: Vec<SystemDescriptor>,
+    order: Vec<usize>,
+    stages: Vec<Vec<usize>>,
+    conflicts: Vec<Conflict>,
+    mode: ExecutionMode,
+}
+
+impl Schedule {
+    pub fn add(&mut self, system: SystemDescriptor) -> &mut Self {
+        self.systems.push(system);
+        self
+    }
+    // Sorts the systems. Must be called after the last `add` and before `run`.
+    pub fn build(&mut self) -> Result<(), ScheduleError> {
+        let mut index = HashMap::new();
+        for (i, system) in self.systems.iter().enumerate() {
+            if index.insert(system.label, i).is_some() {
+                return Err(ScheduleError::DuplicateLabel(system.label));
+            }
+            if let Some(data) = system.access.overlap() {
+                return Err(ScheduleError::AliasedBorrow {
+                    system: system.label,
+                    data: data.to_string(),
+                });
+            }
+        }
+        let lookup = |system: &SystemDescriptor, label: &'static str| match index.get(label) {
+            Some(&i) => Ok(i),
+            None => Err(ScheduleError::UnknownLabel {
+                system: system.label,
+                label: label,
+            }),
+        };
+
+        // edges[i] lists the systems that must run after system i.
+        let mut edges = vec![BTreeSet::new(); self.systems.len()];
+        for (i, system) in self.systems.iter().enumerate() {
+            for &label in &system.before {
+                edges[i].insert(lookup(system, label)?);
+            }
+            for &label in &system.after {
+                edges[lookup(system, label)?].insert(i);
+            }
+        }
+
+        let mut incoming = vec![0; self.systems.len()];
+        for targets in &edges {
+            for &j in targets {
+                incoming[j] += 1;
+            }
+        }
+        let mut ready: BTreeSet<usize> = (0..self.systems.len())
+            .filter(|&i| incoming[i] == 0)
+            .collect();
+        let mut order = Vec::new();
+        while let Some(&i) = ready.iter().next() {
+            ready.remove(&i);
+            order.push(i);
+            for &j in &edges[i] {
+                incoming[j] -= 1;
+                if incoming[j] == 0 {
+                    ready.insert(j);
+                }
+            }
+        }
+        if order.len() < self.systems.len() {
+            return Err(ScheduleError::Cycle(self.find_cycle(&edges, &incoming)));
+        }
+        self.conflicts = self.find_conflicts(&edges, &order);
+        self.stages = self.find_stages(&edges, &order);
+        self.order = order;
+        Ok(())
+    }
+    pub fn set_mode(&mut self, mode: ExecutionMode) {
+        self.mode = mode;
+    }
+    // Conflicts found by the last `build`, in the order the systems run.
+    pub fn conflicts(&self) -> &[Conflict] {
+        &self.conflicts
+    }
+    pub fn run(&self, world: &World) {
+        assert!(
+            self.order.len() == self.systems.len(),
+            "schedule is not built"
+        );
+        match self.mode {
+            ExecutionMode::SingleThreaded => {
+                for &i in &self.order {
+                    (self.systems[i].run)(world);
+                }
+            }
+            ExecutionMode::Parallel => {
+                for stage in &self.stages {
+                    self.run_stage(stage, world);
+                }
+            }
+        }
+    }
+    #[cfg(all(feature = "parallel", not(target_arch = "wasm32")))]
+    fn run_stage(&self, stage: &[usize], world: &World) {
+        use rayon::prelude::*;
+        if stage.len() == 1 {
+            (self.systems[stage[0]].run)(world);
+        } else {
+            stage.par_iter().for_each(|&i| (self.systems[i].run)(world));
+        }
+    }
+    #[cfg(not(all(feature = "parallel", not(target_arch = "wasm32"))))]
+    fn run_stage(&self, stage: &[usize], world: &World) {
+        for &i in stage {
+            (self.systems[i].run)(world);
+        }
+    }
+    // Groups the sorted systems into stages that run one after another. A
+    // system goes in the stage after the last one holding a system it is
+    // ordered after or conflicts with, so conflicting systems keep their
+    // sorted order.
+    fn find_stages(&self, edges: &[BTreeSet<usize>], order: &[usize]) -> Vec<Vec<usize>> {
+        let mut stage_of = vec![0; self.systems.len()];
+        let mut stages: Vec<Vec<usize>> = Vec::new();
+        for (n, &i) in order.iter().enumerate() {
+            let access = &self.systems[i].access;
+            let stage = order[..n]
+                .iter()
+                .filter(|&&j| {
+                    edges[j].contains(&i)
+                        || !access.conflicts_with(&self.systems[j].access).is_empty()
+                })
+                .map(|&j| stage_of[j] + 1)
+                .max()
+                .unwrap_or(0);
+            if stage == stages.len() {
+                stages.push(Vec::new());
+            }
+            stages[stage].push(i);
+            stage_of[i] = stage;
+        }
+        stages
+    }
+    fn find_conflicts(&self, edges: &[BTreeSet<usize>], order: &[usize]) -> Vec<Conflict> {
+        // Walking the sorted order backwards, each system reaches the union
+        // of what its successors reach.
+        let mut reaches = vec![BTreeSet::new(); self.systems.len()];
+        for &i in order.iter().rev() {
+            let mut reached = BTreeSet::new();
+            for &j in &edges[i] {
+                reached.insert(j);
+                reached.extend(&reaches[j]);
+            }
+            reaches[i] = reached;
+        }
+        let mut conflicts = Vec::new();
+        for (n, &i) in order.iter().enumerate() {
+            for &j in &order[n + 1..] {
+                if reaches[i].contains(&j) {
+                    continue;
+                }
+                let access = &self.systems[i].access;
+                for (data, kind) in access.conflicts_with(&self.systems[j].access) {
+                    conflicts.push(Conflict {
+                        systems: [self.systems[i].label, self.systems[j].label],
+                        data: data.to_string(),
+                        kind: kind,
+                    });
+                }
+            }
+        }
+        conflicts
+    }
+    // Every system left with incoming edges after the sort is on or behind a
+    // cycle. Walking backwards along those edges must come round to a system
+    // already visited.
+    fn find_cycle(&self, edges: &[BTreeSet<usize>], incoming: &[usize]) -> Vec<&'static str> {
+        let mut previous = vec![None; self.systems.len()];
+        for (i, targets) in edges.iter().enumerate() {
+            if incoming[i] == 0 {
+                continue;
+            }
+            for &j in targets {
+                if incoming[j] > 0 && previous[j].is_none() {
+                    previous[j] = Some(i);
+                }
+            }
+        }
+        let mut path = Vec::new();
+        let mut current = (0..self.systems.len()).find(|&i| incoming[i] > 0).unwrap();
+        while !path.contains(&current) {
+            path.push(current);
+            current = previous[current].unwrap();
+        }
+        let start = path.iter().position(|&i| i == current).unwrap();
+        let mut cycle: Vec<_> = path[start..].iter().rev().cloned().collect();
+        // Start from the system added first so the message is stable.
+        let first = (0..cycle.len()).min_by_key(|&i| cycle[i]).unwrap();
+        cycle.rotate_left(first);
+        cycle.push(cycle[0]);
+        cycle.iter().map(|&i| self.systems[i].label).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct A;
+    struct B;
+
+    impl SystemProcess for System<CContainer<A>, ()> {
+        fn process(_: &mut Self::Update, _: &Self::Refer) {}
+    }
+
+    impl SystemProcess for System<CContainer<B>, ()> {
+        fn process(_: &mut Self::Update, _: &Self::Refer) {}
+    }
+
+    impl SystemProcess for System<CContainer<B>, CContainer<A>> {
+        fn process(_: &mut Self::Update, _: &Self::Refer) {}
+    }
+
+    impl SystemProcess for System<CContainer<A>, CContainer<A>> {
+        fn process(_: &mut Self::Update, _: &Self::Refer) {}
+    }
+
+    fn writes_a(label: &'static str) -> SystemDescriptor {
+        system::<CContainer<A>, ()>(label)
+    }
+
+    fn writes_b(label: &'static str) -> SystemDescriptor {
+        system::<CContainer<B>, ()>(label)
+    }
+
+    fn reads_a(label: &'static str) -> SystemDescriptor {
+        system::<CContainer<B>, CContainer<A>>(label)
+    }
+
+    fn conflicts(systems: Vec<SystemDescriptor>) -> Vec<(String, ConflictKind)> {
+        let mut schedule = Schedule::default();
+        for system in systems {
+            schedule.add(system);
+        }
+        schedule.build().unwrap();
+        schedule
+            .conflicts()
+            .iter()
+            .map(|conflict| (conflict.to_string(), conflict.kind))
+            .collect()
+    }
+
+    // The labels in the order the systems run.
+    fn build(systems: Vec<SystemDescriptor>) -> Result<Vec<&'static str>, ScheduleError> {
+        let mut schedule = Schedule::default();
+        for system in systems {
+            schedule.add(system);
+        }
+        schedule.build()?;
+        Ok(schedule
+            .order
+            .iter()
+            .map(|&i| schedule.systems[i].label)
+            .collect())
+    }
+
+    #[test]
+    fn unordered_systems_keep_the_order_they_were_added_in() {
+        let order = build(vec![
+            writes_a("first"),
+            writes_b("second"),
+            writes_b("third").before("second"),
+            writes_a("fourth"),
+        ])
+        .unwrap();
+        assert_eq!(order, vec!["first", "third", "second", "fourth"]);
+    }
+
+    #[test]
+    fn cycles_are_reported_from_the_first_system_in_them() {
+        let err = build(vec![
+            writes_a("unrelated"),
+            writes_b("third").before("first"),
+            writes_b("first").before("second"),
+            writes_a("second").after("unrelated").before("third"),
+        ])
+        .unwrap_err();
+        match &err {
+            ScheduleError::Cycle(labels) => {
+                assert_eq!(labels, &vec!["third", "first", "second", "third"])
+            }
+            _ => panic!("unexpected error: {}", err),
+        }
+        assert_eq!(
+            err.to_string(),
+            "systems are ordered in a cycle: third -> first -> second -> third"
+        );
+    }
+
+    #[test]
+    fn labels_must_be_unique() {
+        let err = build(vec![writes_a("twice"), writes_b("twice")]).unwrap_err();
+        match &err {
+            ScheduleError::DuplicateLabel(label) => assert_eq!(*label, "twice"),
+            _ => panic!("unexpected error: {}", err),
+        }
+        assert_eq!(err.to_string(), "more than one system is labelled `twice`");
+    }
+
+    #[test]
+    fn orders_must_name_added_systems() {
+        let err = build(vec![writes_a("first").after("missing")]).unwrap_err();
+        match &err {
+            ScheduleError::UnknownLabel { system, label } => {
+                assert_eq!((*system, *label), ("first", "missing"))
+            }
+            _ => panic!("unexpected error: {}", err),
+        }
+        assert_eq!(
+            err.to_string(),
+            "`first` is ordered against unknown system `missing`"
+        );
+    }
+
+    #[test]
+    fn unordered_writers_conflict() {
+        assert_eq!(
+            conflicts(vec![writes_a("first"), writes_a("second")]),
+            vec![(
+                concat!(
+                    "`first` and `second` both write `ComponentContainer<A>` ",
+                    "with no declared order"
+                )
+                .to_string(),
+                ConflictKind::WriteWrite
+            )]
+        );
+    }
+
+    #[test]
+    fn unordered_reader_and_writer_conflict() {
+        assert_eq!(
+            conflicts(vec![reads_a("reader"), writes_a("writer")]),
+            vec![(
+                concat!(
+                    "`reader` and `writer` read and write `ComponentContainer<A>` ",
+                    "with no declared order"
+                )
+                .to_string(),
+                ConflictKind::ReadWrite
+            )]
+        );
+    }
+
+    #[test]
+    fn transitively_ordered_systems_do_not_conflict() {
+        let found = conflicts(vec![
+            writes_a("first").before("between"),
+            writes_b("between"),
+            writes_a("last").after("between"),
+        ]);
+        assert!(found.is_empty(), "{:?}", found);
+    }
+
+    #[test]
+    fn systems_cannot_borrow_mutably_twice() {
+        let err = build(vec![system::<CContainer<A>, CContainer<A>>("aliased")]).unwrap_err();
+        match &err {
+            ScheduleError::AliasedBorrow { system, data } => {
+                assert_eq!(
+                    (*system, data.as_str()),
+                    ("aliased", "ComponentContainer<A>")
+                )
+            }
+            _ => panic!("unexpected error: {}", err),
+        }
+        assert_eq!(
+            err.to_string(),
+            "`aliased` borrows `ComponentContainer<A>` mutably more than once"
+        );
+    }
+}
@@ -0,0 +1,559 @@
+use crate::builder::*;
+use crate::commands::*;
+use crate::components::*;
+use crate::events::*;
+use crate::geom::*;
+use crate::prefab::*;
+use crate::registry::*;
+use crate::replay::*;
+use crate::scene::*;
+use crate::schedule::*;
+use crate::time::*;
+use crate::world::*;
+use crate::{CharacterAnimID, CharacterAnimator};
+use std::error::Error;
+use std::f32::consts::*;
+use std::path::Path;
+
+// The game without a window: the world, the level loaded into it and the
+// systems that advance it. `Game` drives one from quicksilver; tests and the
+// headless runner step it directly.
+#[derive(Default)]
+pub struct Simulation {
+    world: World,
+    prefabs: Prefabs,
+    registry: Registry,
+    schedule: Schedule,
+    recorder: Option<Recorder>,
+    replay: Option<Replay>,
+    log_state_hash: bool,
+}
+
+impl Simulation {
+    // Loads the level from this crate's `static` directory, wherever it is
+    // run from.
+    pub fn new() -> Result<Self, Box<dyn Error>> {
+        Self::with_assets(concat!(env!("CARGO_MANIFEST_DIR"), "/static"))
+    }
+
+    // Loads the level from `prefabs.json` and `scene.json` in `assets`.
+    pub fn with_assets<P: AsRef<Path>>(assets: P) -> Result<Self, Box<dyn Error>> {
+        let mut simulation = Self::default();
+        simulation.register_components();
+        simulation.insert_resources();
+        simulation.register_prefabs();
+        simulation.register_saved_types();
+        simulation.register_state_hashes();
+        // Logging the state hash after every step lets two runs be diffed to
+        // find the first step where they went apart.
+        simulation.log_state_hash = std::env::var_os("LOG_STATE_HASH").is_some();
+        simulation.build_schedule()?;
+        for conflict in simulation.schedule.conflicts() {
+            log::warn!("{}", conflict);
+        }
+        simulation.load_level(assets.as_ref())?;
+        Ok(simulation)
+    }
+
+    // Advances the world by one fixed step of `Time::delta` seconds.
+    pub fn step(&mut self) {
+        self.world.advance_tick();
+        self.world.resource_mut::<Events<HitEvent>>().update();
+        if let Some(inputs) = self.replay.as_mut().and_then(Replay::next) {
+            self.world.resource_mut::<InputState>().replayed = Some(inputs.into_iter().collect());
+        }
+
+        self.schedule.run(&self.world);
+        if let Some(recorder) = &mut self.recorder {
+            recorder.record(&self.world.storage::<Input>());
+        }
+
+        self.world.clear_trackers();
+        self.world.apply_commands();
+        if self.log_state_hash {
+            log::info!("tick {} state hash {:016x}", self.tick(), self.state_hash());
+        }
+    }
+
+    // Steps `ticks` times. Before each step `script` is given the number of
+    // steps this call has run so far and returns the keys held during the
+    // next one.
+    pub fn run<F: FnMut(u32) -> Input>(&mut self, ticks: u32, mut script: F) {
+        for step in 0..ticks {
+            let input = script(step);
+            self.set_input(input);
+            self.step();
+        }
+    }
+
+    // The keys held from the next step on, copied into every `Input`.
+    pub fn set_input(&mut self, input: Input) {
+        self.world.resource_mut::<InputState>().keys = input;
+    }
+
+    pub fn tick(&self) -> u32 {
+        self.world.tick()
+    }
+
+    // The saved form of the world, as written by `save`.
+    pub fn state(&self) -> Result<serde_json::Value, Box<dyn Error>> {
+        Ok(serde_json::to_value(self.world.serialize(&self.registry)?)?)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Box<dyn Error>> {
+        let data = self.world.serialize(&self.registry)?;
+        std::fs::write(path, serde_json::to_string_pretty(&data)?)?;
+        Ok(())
+    }
+
+    pub fn load<P: AsRef<Path>>(&mut self, path: P) -> Result<(), Box<dyn Error>> {
+        let data: WorldData = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+        self.world.deserialize(&self.registry, &data)?;
+        Ok(())
+    }
+
+    // Adds an instance of the prefab `name` at (`x`, `y`).
+    pub fn spawn(&mut self, name: &str, x: f32, y: f32) -> Result<(), Box<dyn Error>> {
+        self.prefabs
+            .spawn(&mut self.world, name)?
+            .with(Position(Vector::new(x, y)));
+        Ok(())
+    }
+
+    // Writes the prefab instances in the world out as a scene file that
+    // `load_level` can start from.
+    pub fn save_scene<P: AsRef<Path>>(&self, path: P) -> Result<(), Box<dyn Error>> {
+        save_scene(&self.world, &self.prefabs, path)?;
+        Ok(())
+    }
+
+    pub fn state_hash(&self) -> u64 {
+        self.world.state_hash()
+    }
+
+    pub fn set_log_state_hash(&mut self, log_state_hash: bool) {
+        self.log_state_hash = log_state_hash;
+    }
+
+    // Records the input of every controlled entity from the current state on.
+    pub fn start_recording(&mut self) -> Result<(), Box<dyn Error>> {
+        self.recorder = Some(Recorder::new(self.world.serialize(&self.registry)?));
+        Ok(())
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recorder.is_some()
+    }
+
+    // Returns everything recorded since `start_recording`, or `None` if
+    // nothing was being recorded.
+    pub fn stop_recording(&mut self) -> Option<Recording> {
+        let recorder = self.recorder.take()?;
+        Some(recorder.finish(self.state_hash()))
+    }
+
+    // Loads the state `recording` started from and plays its inputs back over
+    // the following steps. `set_input` has no effect until it is finished.
+    pub fn start_replay(&mut self, recording: Recording) -> Result<(), Box<dyn Error>> {
+        let (start, replay) = Replay::new(recording);
+        self.world.deserialize(&self.registry, &start)?;
+        self.replay = Some(replay);
+        Ok(())
+    }
+
+    pub fn is_replaying(&self) -> bool {
+        self.replay.is_some()
+    }
+
+    // Ends a replay once all of its steps have run and checks that the world
+    // is in the recorded state. Returns `None` while steps remain.
+    pub fn finish_replay(&mut self) -> Option<Result<(), ReplayError>> {
+        if !self.replay.as_ref()?.is_finished() {
+            return None;
+        }
+        let replay = self.replay.take()?;
+        self.world.resource_mut::<InputState>().replayed = None;
+        Some(replay.check(self.state_hash()))
+    }
+
+    // Plays back all of `recording` at once.
+    pub fn replay(&mut self, recording: Recording) -> Result<(), Box<dyn Error>> {
+        self.start_replay(recording)?;
+        loop {
+            if let Some(result) = self.finish_replay() {
+                return Ok(result?);
+            }
+            self.step();
+        }
+    }
+
+    #[cfg(feature = "window")]
+    pub(crate) fn world(&self) -> &World {
+        &self.world
+    }
+
+    // Copies the saved types, as `save` would, without serializing them.
+    pub fn snapshot(&self) -> Snapshot {
+        self.world.snapshot(&self.registry)
+    }
+
+    pub fn restore(&mut self, snapshot: &Snapshot) {
+        self.world.restore(&self.registry, snapshot);
+    }
+
+    fn register_components(&mut self) {
+        self.world.register::<Input>();
+        self.world.register::<Team>();
+        self.world.register::<SwordCollider>();
+        self.world.register::<BodyWeaponCollider>();
+        self.world.register::<BodyDefenseCollider>();
+        self.world.register::<MoveTarget>();
+        self.world.register_sparse::<Position>();
+        self.world.register_sparse::<PreviousPosition>();
+        self.world.register_sparse::<Direction>();
+        self.world.register_sparse::<Velocity>();
+        self.world.register_sparse::<CharacterAnimator>();
+        self.world.register_sparse::<CharacterView>();
+        // Movement, collision and drawing all join these.
+        self.world
+            .register_archetype::<(Position, Velocity, Direction, CharacterView)>();
+    }
+
+    // Everything that makes up a game in progress. The `InputState` resource
+    // is left out so that loading does not leave keys stuck, and the command
+    // buffer is always empty between frames.
+    fn register_saved_types(&mut self) {
+        self.registry.register_component::<Input>("Input");
+        self.registry.register_component::<Team>("Team");
+        self.registry
+            .register_component::<SwordCollider>("SwordCollider");
+        self.registry
+            .register_component::<BodyWeaponCollider>("BodyWeaponCollider");
+        self.registry
+            .register_component::<BodyDefenseCollider>("BodyDefenseCollider");
+        self.registry.register_component::<MoveTarget>("MoveTarget");
+        self.registry.register_component::<Position>("Position");
+        self.registry
+            .register_component::<PreviousPosition>("PreviousPosition");
+        self.registry.register_component::<Direction>("Direction");
+        self.registry.register_component::<Velocity>("Velocity");
+        self.registry
+            .register_component::<CharacterAnimator>("CharacterAnimator");
+        self.registry
+            .register_component::<CharacterView>("CharacterView");
+        self.registry.register_component::<PrefabName>("PrefabName");
+        self.registry
+            .register_resource::<Events<HitEvent>>("HitEvents");
+        self.registry
+            .register_resource::<EventReader<HitEvent, CharacterAnimator>>("AnimatorHitReader");
+    }
+
+    // What two runs must agree on for one to be a faithful replay of the
+    // other. Views are left out as they only follow these.
+    fn register_state_hashes(&mut self) {
+        self.world.register_state_hash::<Input>();
+        self.world.register_state_hash::<Team>();
+        self.world.register_state_hash::<Position>();
+        self.world.register_state_hash::<Direction>();
+        self.world.register_state_hash::<Velocity>();
+        self.world.register_state_hash::<MoveTarget>();
+        self.world.register_state_hash::<CharacterAnimator>();
+        self.world.register_state_hash::<SwordCollider>();
+        self.world.register_state_hash::<BodyWeaponCollider>();
+        self.world.register_state_hash::<BodyDefenseCollider>();
+        self.world
+            .register_resource_state_hash::<Events<HitEvent>>();
+        self.world
+            .register_resource_state_hash::<EventReader<HitEvent, CharacterAnimator>>();
+    }
+
+    fn insert_resources(&mut self) {
+        self.world.insert_resource(Commands::default());
+        self.world.insert_resource(InputState::default());
+        self.world.insert_resource(Time::default());
+        self.world.insert_resource(Events::<HitEvent>::default());
+        self.world
+            .insert_resource(EventReader::<HitEvent, CharacterAnimator>::default());
+    }
+
+    fn wait_animation() -> Animation<CharacterAnimFrame> {
+        let mut frames = Vec::new();
+
+        for d in 0..20 {
+            let s = ((d as f32 / 20f32 * PI).sin() * 0.2f32 - 0.1f32) + 1.0f32;
+            frames.push(CharacterAnimFrame {
+                radius_scale: s,
+                weapon_direction: 0f32,
+                ..Default::default()
+            });
+        }
+
+        Animation::new(true, frames)
+    }
+
+    fn attack_animation() -> Animation<CharacterAnimFrame> {
+        let mut frames = Vec::new();
+
+        for f in 0..12 {
+            let dir = -FRAC_PI_4 - FRAC_PI_8 + f as f32 * FRAC_PI_8 / 2f32;
+            frames.push(CharacterAnimFrame {
+                radius_scale: 1f32,
+                weapon_direction: dir,
+                ..Default::default()
+            });
+        }
+        Animation::new(false, frames)
+    }
+
+    fn damaged_animation() -> Animation<CharacterAnimFrame> {
+        let mut frames = Vec::new();
+
+        for _ in 0..12 {
+            frames.push(CharacterAnimFrame {
+                radius_scale: 1f32,
+                move_forward: -480f32,
+                ..Default::default()
+            });
+        }
+
+        Animation::new(false, frames)
+    }
+
+    fn character_animator() -> CharacterAnimator {
+        let mut animator = CharacterAnimator::default();
+        animator.register(CharacterAnimID::Wait, Self::wait_animation());
+        animator.register(CharacterAnimID::Attack, Self::attack_animation());
+        animator.register(CharacterAnimID::Damaged, Self::damaged_animation());
+        animator.play(CharacterAnimID::Wait);
+        animator
+    }
+
+    fn register_prefabs(&mut self) {
+        self.prefabs.register::<Input>("Input");
+        self.prefabs.register::<Team>("Team");
+        self.prefabs.register::<SwordCollider>("SwordCollider");
+        self.prefabs
+            .register::<BodyWeaponCollider>("BodyWeaponCollider");
+        self.prefabs
+            .register::<BodyDefenseCollider>("BodyDefenseCollider");
+        self.prefabs.register::<MoveTarget>("MoveTarget");
+        self.prefabs.register::<Position>("Position");
+        self.prefabs.register::<Direction>("Direction");
+        self.prefabs.register::<Velocity>("Velocity");
+        self.prefabs.register::<CharacterView>("CharacterView");
+        self.prefabs.register_bundle::<CharacterBundle>("Character");
+        self.prefabs
+            .register_with("CharacterAnimator", |value, world, entity_id| {
+                match value.as_str() {
+                    Some("character") => {
+                        world.insert(entity_id, Self::character_animator());
+                        Ok(())
+                    }
+                    _ => Err(PrefabError::InvalidComponent {
+                        component: "CharacterAnimator".to_string(),
+                        message: format!("unknown animation set {}", value),
+                    }),
+                }
+            });
+    }
+
+    fn build_schedule(&mut self) -> Result<(), ScheduleError> {
+        self.schedule
+            .add(system::<CContainer<Input>, InputState>("copy_input"))
+            .add(
+                system::<
+                    CContainer<SwordCollider>,
+                    (&CContainer<CharacterView>, &CContainer<CharacterAnimator>),
+                >("sword_collider")
+                .before("animator_input")
+                .before("view_animation"),
+            )
+            .add(
+                system::<CContainer<BodyWeaponCollider>, CContainer<CharacterView>>(
+                    "body_weapon_collider",
+                )
+                .before("view_animation"),
+            )
+            .add(
+                system::<CContainer<BodyDefenseCollider>, CContainer<CharacterView>>(
+                    "body_defense_collider",
+                )
+                .before("view_animation"),
+            )
+            .add(
+                system::<
+                    Events<HitEvent>,
+                    (
+                        &CContainer<BodyDefenseCollider>,
+                        &CContainer<SwordCollider>,
+                        &CContainer<BodyWeaponCollider>,
+                        &CContainer<Team>,
+                    ),
+                >("hit_detection")
+                .after("sword_collider")
+                .after("body_weapon_collider")
+                .after("body_defense_collider"),
+            )
+            .add(
+                system::<CContainer<MoveTarget>, (&CContainer<Team>, &CContainer<Position>)>(
+                    "move_target",
+                ),
+            )
+            .add(
+                system::<CContainer<Velocity>, CContainer<Input>>("velocity_input")
+                    .after("copy_input"),
+            )
+            .add(
+                system::<CContainer<Velocity>, (&CContainer<Position>, &CContainer<MoveTarget>)>(
+                    "velocity_chase",
+                )
+                .after("velocity_input")
+                .after("move_target"),
+            )
+            .add(
+                system::<
+                    CContainer<Velocity>,
+                    (&CContainer<CharacterView>, &CContainer<CharacterAnimator>),
+                >("velocity_animation")
+                .after("velocity_chase")
+                .before("animator_input"),
+            )
+            .add(
+                system::<CContainer<PreviousPosition>, CContainer<Position>>(
+                    "store_previous_position",
+                )
+                .before("apply_velocity"),
+            )
+            .add(
+                system::<
+                    Commands,
+                    (
+                        &CContainer<Position>,
+                        RemovedComponents<Position>,
+                        &CContainer<PreviousPosition>,
+                    ),
+                >("track_previous_position")
+                .before("store_previous_position"),
+            )
+            .add(
+                system::<CContainer<Position>, (&CContainer<Velocity>, &Time)>("apply_velocity")
+                    .after("velocity_animation"),
+            )
+            .add(
+                system::<CContainer<Direction>, CContainer<Input>>("direction_input")
+                    .after("copy_input"),
+            )
+            .add(
+                system::<CContainer<Direction>, (&CContainer<Position>, &CContainer<MoveTarget>)>(
+                    "direction_chase",
+                )
+                .after("direction_input")
+                .after("apply_velocity")
+                .after("move_target"),
+            )
+            .add(
+                system::<CContainer<CharacterAnimator>, CContainer<Input>>("animator_input")
+                    .after("copy_input"),
+            )
+            .add(
+                system::<
+                    (
+                        &mut CContainer<CharacterAnimator>,
+                        &mut EventReader<HitEvent, CharacterAnimator>,
+                    ),
+                    Events<HitEvent>,
+                >("animator_hit")
+                .after("animator_input")
+                .after("hit_detection"),
+            )
+            .add(
+                system::<CContainer<CharacterAnimator>, ()>("animator_update")
+                    .after("animator_hit"),
+            )
+            .add(
+                system::<CContainer<CharacterView>, CContainer<CharacterAnimator>>(
+                    "view_animation",
+                )
+                .after("animator_update"),
+            )
+            .add(
+                system::<CContainer<CharacterView>, (&CContainer<Position>, &CContainer<Direction>)>(
+                    "view_transform",
+                )
+                .after("apply_velocity")
+                .after("direction_chase")
+                .after("view_animation"),
+            );
+        // Running systems one at a time makes a tick easier to step through
+        // in a debugger.
+        if std::env::var_os("SINGLE_THREADED").is_some() {
+            self.schedule.set_mode(ExecutionMode::SingleThreaded);
+        }
+        self.schedule.build()
+    }
+
+    // Native builds read prefabs and the scene at startup so they can be
+    // edited without recompiling. The web build has no file system and embeds
+    // them instead.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn load_level(&mut self, assets: &Path) -> Result<(), PrefabError> {
+        self.prefabs.load_file(assets.join("prefabs.json"))?;
+        load_scene(&mut self.world, &self.prefabs, assets.join("scene.json"))?;
+        Ok(())
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    fn load_level(&mut self, _: &Path) -> Result<(), PrefabError> {
+        self.prefabs
+            .load_str(include_str!("../static/prefabs.json"))?;
+        Scene::parse(include_str!("../static/scene.json"))?
+            .spawn(&mut self.world, &self.prefabs)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entities::*;
+    use serde_json::Value;
+
+    // Each animator's entity with the animation and frame it is at.
+    fn animators(simulation: &Simulation) -> Vec<(EntityID, Value, Value)> {
+        let animators = simulation.world.storage::<CharacterAnimator>();
+        let mut animators: Vec<_> = animators
+            .iter()
+            .map(|(entity_id, animator)| {
+                let animator = serde_json::to_value(animator).unwrap();
+                (
+                    entity_id,
+                    animator["playing_id"].clone(),
+                    animator["current_frame"].clone(),
+                )
+            })
+            .collect();
+        animators.sort_by_key(|(entity_id, _, _)| entity_id.index());
+        animators
+    }
+
+    #[test]
+    fn serialize_round_trip() {
+        let mut simulation = Simulation::new().unwrap();
+        simulation.run(40, |step| Input {
+            attack: step == 30,
+            ..Input::default()
+        });
+        let data = simulation.world.serialize(&simulation.registry).unwrap();
+        let json = serde_json::to_string(&data).unwrap();
+
+        let mut loaded = Simulation::new().unwrap();
+        let data: WorldData = serde_json::from_str(&json).unwrap();
+        loaded.world.deserialize(&loaded.registry, &data).unwrap();
+
+        let before = animators(&simulation);
+        assert!(before.iter().any(|(_, _, frame)| frame != 0));
+        assert_eq!(animators(&loaded), before);
+        assert_eq!(loaded.tick(), simulation.tick());
+        assert_eq!(loaded.state_hash(), simulation.state_hash());
+    }
+}
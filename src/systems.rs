@@ -1,5 +1,14 @@
+use crate::commands::*;
 use crate::components::*;
+use crate::entities::*;
+use crate::events::*;
+use crate::geom::*;
+use crate::query::*;
+use crate::schedule::*;
+use crate::time::*;
 use crate::*;
+#[cfg(all(feature = "parallel", not(target_arch = "wasm32")))]
+use rayon::prelude::*;
 use std::marker::PhantomData;
 
 pub(crate) trait SystemInterface {
@@ -18,58 +27,41 @@ impl<U, R> SystemInterface for System<U, R> {
     type Refer = R;
 }
 
-pub(crate) struct ForObserverSet();
-pub(crate) struct ForObserverCheck();
-
-impl<V, C> SystemProcess
-    for System<CContainer<ValueObserver<V, C>>, (&CContainer<C>, ForObserverSet)>
-where
-    V: PartialEq + Copy,
-{
-    fn process(observers: &mut Self::Update, (components, _): &Self::Refer) {
-        observers
-            .iter_mut()
-            .zip_entity(components)
-            .for_each(|(_, observer, component)| {
-                observer.set(component);
-            });
+impl SystemProcess for System<CContainer<Input>, InputState> {
+    fn process(inputs: &mut Self::Update, input_state: &Self::Refer) {
+        inputs.iter_mut().for_each(|(entity_id, input)| {
+            *input = match &input_state.replayed {
+                Some(replayed) => replayed.get(&entity_id).copied().unwrap_or_default(),
+                None => input_state.keys,
+            };
+        });
     }
 }
 
-impl<V, C> SystemProcess
-    for System<CContainer<ValueObserver<V, C>>, (&CContainer<C>, ForObserverCheck)>
-where
-    V: PartialEq + Copy,
-{
-    fn process(observers: &mut Self::Update, components: &Self::Refer) {
-        observers
-            .iter_mut()
-            .zip_entity(components.0)
-            .for_each(|(_, observer, component)| {
-                observer.check(component);
-            });
-    }
-}
+// Velocities are in pixels per second.
+const WALK_SPEED: f32 = 120f32;
+// Fraction of the distance to its target a chasing character covers per
+// second.
+const CHASE_RATE: f32 = 1.2f32;
 
 impl SystemProcess for System<CContainer<Velocity>, CContainer<Input>> {
     fn process(velocities: &mut Self::Update, inputs: &Self::Refer) {
-        velocities
-            .iter_mut()
-            .zip_entity(inputs)
+        (velocities, inputs)
+            .query()
             .for_each(|(_, velocity, input)| {
                 velocity.x = 0f32;
                 velocity.y = 0f32;
                 if input.left {
-                    velocity.x = -2f32;
+                    velocity.x = -WALK_SPEED;
                 }
                 if input.right {
-                    velocity.x = 2f32;
+                    velocity.x = WALK_SPEED;
                 }
                 if input.up {
-                    velocity.y = -2f32;
+                    velocity.y = -WALK_SPEED;
                 }
                 if input.down {
-                    velocity.y = 2f32;
+                    velocity.y = WALK_SPEED;
                 }
             });
     }
@@ -77,16 +69,15 @@ impl SystemProcess for System<CContainer<Velocity>, CContainer<Input>> {
 
 impl SystemProcess for System<CContainer<MoveTarget>, (&CContainer<Team>, &CContainer<Position>)> {
     fn process(move_targets: &mut Self::Update, (teams, positions): &Self::Refer) {
-        move_targets
-            .iter_mut()
-            .zip_entity2(teams, positions)
+        (move_targets, *teams, *positions)
+            .query()
             .for_each(|(_, target, self_team, self_pos)| {
                 teams
                     .iter()
                     .filter(|(_, team)| team.team_id() != self_team.team_id())
                     .for_each(|(entity_id, _)| {
                         if let Some(pos) = CContainer::<Position>::get(positions, entity_id) {
-                            let distance = pos.distance((self_pos.x, self_pos.y));
+                            let distance = pos.distance(**self_pos);
                             if distance < 100f32 {
                                 target.x = pos.x;
                                 target.y = pos.y;
@@ -104,15 +95,14 @@ impl SystemProcess
     for System<CContainer<Velocity>, (&CContainer<Position>, &CContainer<MoveTarget>)>
 {
     fn process(velocities: &mut Self::Update, pos_tgt: &Self::Refer) {
-        velocities
-            .iter_mut()
-            .zip_entity2(pos_tgt.0, pos_tgt.1)
+        (velocities, pos_tgt.0, pos_tgt.1)
+            .query()
             .for_each(|(_, vel, pos, target)| {
                 let mut tmp = Vector::default();
                 tmp.x = target.x - pos.x;
                 tmp.y = target.y - pos.y;
-                vel.x = tmp.x / 50f32;
-                vel.y = tmp.y / 50f32;
+                vel.x = tmp.x * CHASE_RATE;
+                vel.y = tmp.y * CHASE_RATE;
             });
     }
 }
@@ -121,12 +111,11 @@ impl SystemProcess
     for System<CContainer<Direction>, (&CContainer<Position>, &CContainer<MoveTarget>)>
 {
     fn process(directions: &mut Self::Update, (positions, targets): &Self::Refer) {
-        directions
-            .iter_mut()
-            .zip_entity2(positions, targets)
+        (directions, *positions, *targets)
+            .query()
             .for_each(|(_, dir, position, target)| {
-                if position != target {
-                    *dir = (target.y - position.y).atan2(target.x - position.x);
+                if **position != **target {
+                    **dir = (target.y - position.y).atan2(target.x - position.x);
                 }
             });
     }
@@ -136,9 +125,8 @@ impl SystemProcess
     for System<CContainer<Velocity>, (&CContainer<CharacterView>, &CContainer<CharacterAnimator>)>
 {
     fn process(velocities: &mut Self::Update, (views, animators): &Self::Refer) {
-        velocities
-            .iter_mut()
-            .zip_entity2(views, animators)
+        (velocities, *views, *animators)
+            .query()
             .for_each(|(_, velocity, view, animator)| {
                 if let Some(val) = animator.value() {
                     if val.move_forward != 0f32 {
@@ -150,46 +138,81 @@ impl SystemProcess
     }
 }
 
-impl SystemProcess for System<CContainer<Position>, CContainer<Velocity>> {
-    fn process(positions: &mut Self::Update, velocities: &Self::Refer) {
-        positions
-            .iter_mut()
-            .zip_entity(velocities)
-            .for_each(|(_, pos, vel)| {
-                pos.x += vel.x;
-                pos.y += vel.y;
+// Entities that gain a position get a previous position to draw from, and
+// lose it again with the position. Inserted at the end of the step, with the
+// position the step started from.
+impl SystemProcess
+    for System<
+        Commands,
+        (
+            &CContainer<Position>,
+            RemovedComponents<'_, Position>,
+            &CContainer<PreviousPosition>,
+        ),
+    >
+{
+    fn process(
+        commands: &mut Self::Update,
+        (positions, removed_positions, previous_positions): &Self::Refer,
+    ) {
+        (*positions, Added(*positions), Without(*previous_positions))
+            .query()
+            .for_each(|(entity_id, position, _, _)| {
+                commands.insert(entity_id, PreviousPosition(**position));
             });
+        for entity_id in removed_positions.iter() {
+            if previous_positions.contains(*entity_id) {
+                commands.remove::<PreviousPosition>(*entity_id);
+            }
+        }
+    }
+}
+
+impl SystemProcess for System<CContainer<PreviousPosition>, CContainer<Position>> {
+    // Positions that did not change last tick still match what was stored.
+    fn process(previous_positions: &mut Self::Update, positions: &Self::Refer) {
+        (previous_positions, positions, Changed(positions))
+            .query()
+            .for_each(|(_, previous, position, _)| **previous = **position);
+    }
+}
+
+impl SystemProcess for System<CContainer<Position>, (&CContainer<Velocity>, &Time)> {
+    fn process(positions: &mut Self::Update, (velocities, time): &Self::Refer) {
+        (positions, *velocities).query().for_each(|(_, pos, vel)| {
+            pos.x += vel.x * time.delta();
+            pos.y += vel.y * time.delta();
+        });
     }
 }
 
 impl SystemProcess for System<CContainer<Direction>, CContainer<Input>> {
     fn process(directions: &mut Self::Update, inputs: &Self::Refer) {
-        directions
-            .iter_mut()
-            .zip_entity(inputs)
+        (directions, inputs)
+            .query()
             .for_each(|(_, direction, input)| {
                 if input.left {
-                    *direction = PI;
+                    **direction = PI;
                     if input.up {
-                        *direction = FRAC_PI_4 * 5f32;
+                        **direction = FRAC_PI_4 * 5f32;
                     }
                     if input.down {
-                        *direction = FRAC_PI_4 * 3f32;
+                        **direction = FRAC_PI_4 * 3f32;
                     }
                 } else if input.right {
-                    *direction = 0f32;
+                    **direction = 0f32;
                     if input.up {
-                        *direction = FRAC_PI_4 * 7f32;
+                        **direction = FRAC_PI_4 * 7f32;
                     }
                     if input.down {
-                        *direction = FRAC_PI_4;
+                        **direction = FRAC_PI_4;
                     }
                 } else {
                     if input.up {
-                        *direction = FRAC_PI_2 * 3f32;
+                        **direction = FRAC_PI_2 * 3f32;
                     }
                     if input.down {
-                        *direction = FRAC_PI_2;
+                        **direction = FRAC_PI_2;
                     }
                 }
             });
@@ -203,9 +226,8 @@ impl SystemProcess
     >
 {
     fn process(sword_colliders: &mut Self::Update, (views, animators): &Self::Refer) {
-        sword_colliders
-            .iter_mut()
-            .zip_entity2(views, animators)
+        (sword_colliders, *views, *animators)
+            .query()
             .for_each(|(_, collider, view, animator)| {
                 let dir = view.direction + view.weapon_direction;
                 collider.line.a = view.position;
@@ -224,9 +246,8 @@ impl SystemProcess
 
 impl SystemProcess for System<CContainer<BodyWeaponCollider>, CContainer<CharacterView>> {
     fn process(body_weapon_colliders: &mut Self::Update, views: &Self::Refer) {
-        body_weapon_colliders
-            .iter_mut()
-            .zip_entity(views)
+        (body_weapon_colliders, views)
+            .query()
             .for_each(|(_, collider, view)| {
                 collider.circle.pos = view.position;
                 collider.circle.radius = view.radius;
@@ -234,11 +255,22 @@ impl SystemProcess for System<CContainer<BodyWeaponCollider>, CContainer<Charact
     }
 }
 
+impl SystemProcess for System<CContainer<BodyDefenseCollider>, CContainer<CharacterView>> {
+    fn process(body_defenses: &mut Self::Update, character_views: &Self::Refer) {
+        (body_defenses, character_views)
+            .query()
+            .for_each(|(_, body_defense, view)| {
+                body_defense.circle.pos = view.position;
+                body_defense.circle.radius = view.radius;
+            });
+    }
+}
+
 impl SystemProcess
     for System<
-        CContainer<BodyDefenseCollider>,
+        Events<HitEvent>,
         (
-            &CContainer<CharacterView>,
+            &CContainer<BodyDefenseCollider>,
             &CContainer<SwordCollider>,
             &CContainer<BodyWeaponCollider>,
             &CContainer<Team>,
@@ -246,32 +278,39 @@ impl SystemProcess
     >
 {
     fn process(
-        body_defenses: &mut Self::Update,
-        (character_views, sword_colliders, body_weapon_colliders, teams): &Self::Refer,
+        hit_events: &mut Self::Update,
+        (body_defenses, sword_colliders, body_weapon_colliders, teams): &Self::Refer,
     ) {
-        body_defenses
-            .iter_mut()
-            .zip_entity2(character_views, teams)
-            .for_each(|(defense_entity_id, body_defense, view, defense_team)| {
-                body_defense.hit = false;
-                body_defense.circle.pos = view.position;
-                body_defense.circle.radius = view.radius;
-
-                sword_colliders.iter().zip_entity(teams).for_each(
-                    |(sword_entity_id, sword_collider, sword_team)| {
-                        if defense_entity_id == sword_entity_id {
-                            return;
-                        }
-                        if defense_team.team_id() == sword_team.team_id() {
-                            return;
-                        }
-                        if sword_collider.is_collided(body_defense) {
-                            body_defense.hit = true;
-                        }
-                    },
-                );
+        // Every defender is checked against every attacker. Defenders are
+        // split across threads and their hits sent afterwards in defender
+        // order, so the events come out the same either way.
+        let hits_on = |(defense_entity_id, body_defense, defense_team): (
+            EntityID,
+            &BodyDefenseCollider,
+            &Team,
+        )| {
+            let mut hits = Vec::new();
+            (*sword_colliders, *teams).query().for_each(
+                |(sword_entity_id, sword_collider, sword_team)| {
+                    if defense_entity_id == sword_entity_id {
+                        return;
+                    }
+                    if defense_team.team_id() == sword_team.team_id() {
+                        return;
+                    }
+                    if sword_collider.is_collided(body_defense) {
+                        hits.push(HitEvent {
+                            attacker: sword_entity_id,
+                            defender: defense_entity_id,
+                            weapon: Weapon::Sword,
+                            point: sword_collider.hit_point(body_defense),
+                        });
+                    }
+                },
+            );
 
-                body_weapon_colliders.iter().zip_entity(teams).for_each(|(weapon_entity_id, weapon_collider, weapon_team)|{
+            (*body_weapon_colliders, *teams).query().for_each(
+                |(weapon_entity_id, weapon_collider, weapon_team)| {
                     if defense_entity_id == weapon_entity_id {
                         return;
                     }
@@ -279,10 +318,30 @@ impl SystemProcess
                         return;
                     }
                     if weapon_collider.is_collided(body_defense) {
-                        body_defense.hit = true;
+                        hits.push(HitEvent {
+                            attacker: weapon_entity_id,
+                            defender: defense_entity_id,
+                            weapon: Weapon::Body,
+                            point: weapon_collider.hit_point(body_defense),
+                        });
                     }
-                });
-            });
+                },
+            );
+            hits
+        };
+
+        #[cfg(all(feature = "parallel", not(target_arch = "wasm32")))]
+        let hits: Vec<_> = (*body_defenses, *teams)
+            .query()
+            .par_iter()
+            .map(hits_on)
+            .collect();
+        #[cfg(not(all(feature = "parallel", not(target_arch = "wasm32"))))]
+        let hits: Vec<_> = (*body_defenses, *teams).query().map(hits_on).collect();
+
+        for hit in hits.into_iter().flatten() {
+            hit_events.send(hit);
+        }
     }
 }
 
@@ -296,9 +355,8 @@ impl SystemProcess for System<CContainer<CharacterAnimator>, ()> {
 
 impl SystemProcess for System<CContainer<CharacterAnimator>, CContainer<Input>> {
     fn process(animators: &mut Self::Update, inputs: &Self::Refer) {
-        animators
-            .iter_mut()
-            .zip_entity(inputs)
+        (animators, inputs)
+            .query()
             .for_each(|(_, animator, input)| {
                 if let Some(id) = animator.playing_id() {
                     if id == CharacterAnimID::Attack && animator.is_end() {
@@ -312,35 +370,41 @@ impl SystemProcess for System<CContainer<CharacterAnimator>, CContainer<Input>>
     }
 }
 
-impl SystemProcess for System<CContainer<CharacterAnimator>, CContainer<BodyDefenseCollider>> {
-    fn process(animators: &mut Self::Update, defense_colliders: &Self::Refer) {
-        animators
-            .iter_mut()
-            .zip_entity(defense_colliders)
-            .for_each(|(_, animator, collider)| {
-                if let Some(id) = animator.playing_id() {
-                    if id == CharacterAnimID::Damaged && animator.is_end() {
-                        animator.play(CharacterAnimID::Wait);
-                    }
-                    if collider.hit && id != CharacterAnimID::Damaged {
-                        animator.play(CharacterAnimID::Damaged);
-                    }
+impl SystemProcess
+    for System<
+        (
+            &mut CContainer<CharacterAnimator>,
+            &mut EventReader<HitEvent, CharacterAnimator>,
+        ),
+        Events<HitEvent>,
+    >
+{
+    fn process((animators, reader): &mut Self::Update, hit_events: &Self::Refer) {
+        animators.iter_mut().for_each(|(_, animator)| {
+            if let Some(id) = animator.playing_id() {
+                if id == CharacterAnimID::Damaged && animator.is_end() {
+                    animator.play(CharacterAnimID::Wait);
                 }
-            });
+            }
+        });
+        for hit in reader.read(hit_events) {
+            if let Some(animator) = animators.get_mut(hit.defender) {
+                if animator.playing_id() != Some(CharacterAnimID::Damaged) {
+                    animator.play(CharacterAnimID::Damaged);
+                }
+            }
+        }
     }
 }
 
 impl SystemProcess for System<CContainer<CharacterView>, CContainer<CharacterAnimator>> {
     fn process(views: &mut Self::Update, animators: &Self::Refer) {
-        views
-            .iter_mut()
-            .zip_entity(animators)
-            .for_each(|(_, view, animator)| {
-                if let Some(val) = animator.value() {
-                    view.radius_scale = val.radius_scale;
-                    view.weapon_direction = val.weapon_direction;
-                }
-            });
+        (views, animators).query().for_each(|(_, view, animator)| {
+            if let Some(val) = animator.value() {
+                view.radius_scale = val.radius_scale;
+                view.weapon_direction = val.weapon_direction;
+            }
+        });
     }
 }
 
@@ -348,36 +412,12 @@ impl SystemProcess
     for System<CContainer<CharacterView>, (&CContainer<Position>, &CContainer<Direction>)>
 {
     fn process(views: &mut Self::Update, (positions, directions): &Self::Refer) {
-        views
-            .iter_mut()
-            .zip_entity2(positions, directions)
+        (views, *positions, *directions)
+            .query()
             .for_each(|(_, view, pos, dir)| {
                 view.position.x = pos.x;
                 view.position.y = pos.y;
-                view.direction = *dir;
+                view.direction = **dir;
             });
     }
 }
-
-impl SystemProcess for System<Window, CContainer<CharacterView>> {
-    fn process(window: &mut Self::Update, views: &Self::Refer) {
-        views.iter().for_each(|(_, view)| {
-            window.draw(
-                &Circle::new(
-                    (view.position.x, view.position.y),
-                    view.radius * view.radius_scale,
-                ),
-                Col(view.color),
-            );
-            let dir = view.direction + view.weapon_direction;
-            let line_end = (
-                view.position.x + dir.cos() * view.radius * 1.8f32,
-                view.position.y + dir.sin() * view.radius * 1.8f32,
-            );
-            window.draw(
-                &Line::new((view.position.x, view.position.y), line_end),
-                Col(view.color),
-            );
-        });
-    }
-}
@@ -0,0 +1,77 @@
+// Animations advance one frame per step rather than by `delta`, so they were
+// made for this rate and would play faster or slower at any other.
+const STEPS_PER_SECOND: u32 = 60;
+// After a long stall the world skips ahead rather than running steps back to
+// back until it catches up.
+#[cfg(feature = "window")]
+const MAX_STEPS: u32 = 5;
+
+// The simulation clock. The world always advances in steps of the same length,
+// however often the game is updated: `advance` banks real time and says how
+// many steps are due, and whatever is left over becomes `alpha` for drawing
+// between the last two steps. Only the window drives it by real time;
+// without it the clock just steps.
+pub(crate) struct Time {
+    step: f32,
+    #[cfg(feature = "window")]
+    accumulator: f32,
+}
+
+impl Default for Time {
+    fn default() -> Self {
+        Self {
+            step: 1f32 / STEPS_PER_SECOND as f32,
+            #[cfg(feature = "window")]
+            accumulator: 0f32,
+        }
+    }
+}
+
+impl Time {
+    // Seconds simulated by one step. Systems scale per-second rates by this.
+    pub fn delta(&self) -> f32 {
+        self.step
+    }
+    // Adds `real_delta` seconds and returns the number of steps to run.
+    #[cfg(feature = "window")]
+    pub fn advance(&mut self, real_delta: f32) -> u32 {
+        self.accumulator += real_delta.max(0f32);
+        let steps = (self.accumulator / self.step) as u32;
+        self.accumulator -= steps as f32 * self.step;
+        steps.min(MAX_STEPS)
+    }
+    // How far the real time is between the last step and the next one, from
+    // 0 to 1.
+    #[cfg(feature = "window")]
+    pub fn alpha(&self) -> f32 {
+        (self.accumulator / self.step).min(1f32)
+    }
+}
+
+// Measures the real time between updates. The web build has no monotonic
+// clock in std, so it trusts the caller's expected interval instead.
+#[cfg(feature = "window")]
+#[derive(Default)]
+pub(crate) struct Clock {
+    #[cfg(not(target_arch = "wasm32"))]
+    last: Option<std::time::Instant>,
+}
+
+#[cfg(feature = "window")]
+impl Clock {
+    // Seconds since the previous call, or `expected` on the first call.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn delta(&mut self, expected: f32) -> f32 {
+        let now = std::time::Instant::now();
+        let delta = match self.last {
+            Some(last) => (now - last).as_secs_f32(),
+            None => expected,
+        };
+        self.last = Some(now);
+        delta
+    }
+    #[cfg(target_arch = "wasm32")]
+    pub fn delta(&mut self, expected: f32) -> f32 {
+        expected
+    }
+}
@@ -0,0 +1,562 @@
+use crate::archetype::*;
+use crate::builder::*;
+use crate::cell::*;
+use crate::commands::*;
+use crate::components::*;
+use crate::entities::*;
+use crate::hash::*;
+use crate::registry::*;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::any::{Any, TypeId};
+use std::collections::*;
+use std::hash::{Hash, Hasher};
+
+pub(crate) trait AnyStorage: Send + Sync {
+    fn remove_entity(&mut self, entity_id: EntityID);
+    fn set_tick(&mut self, tick: u32);
+    fn clear_removed(&mut self);
+    fn clear(&mut self);
+    fn as_column_mut(&mut self) -> &mut dyn TableColumn;
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+impl<T: Send + Sync + 'static> AnyStorage for CContainer<T> {
+    fn remove_entity(&mut self, entity_id: EntityID) {
+        self.remove(entity_id);
+    }
+    fn set_tick(&mut self, tick: u32) {
+        CContainer::set_tick(self, tick);
+    }
+    fn clear_removed(&mut self) {
+        CContainer::clear_removed(self);
+    }
+    fn clear(&mut self) {
+        CContainer::clear(self);
+    }
+    fn as_column_mut(&mut self) -> &mut dyn TableColumn {
+        self
+    }
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+// Storages and resources sit behind a SyncCell so that a system can borrow the
+// container it updates mutably while borrowing the ones it refers to
+// immutably, and systems on other threads can do the same with other
+// containers.
+#[derive(Default)]
+pub(crate) struct World {
+    tick: u32,
+    entities: EntityAllocator,
+    storages: HashMap<TypeId, SyncCell<Box<dyn AnyStorage>>>,
+    archetypes: Vec<Archetype>,
+    resources: HashMap<TypeId, SyncCell<Box<dyn Any + Send + Sync>>>,
+    state_hashers: Vec<(TypeId, HashStorage)>,
+}
+
+impl World {
+    // Starts a new tick. Components added or changed before this call are
+    // reported by `is_added`/`is_changed` and the `Added`/`Changed` query
+    // filters until the next one.
+    pub fn advance_tick(&mut self) {
+        self.tick += 1;
+        for storage in self.storages.values_mut() {
+            storage.get_mut().set_tick(self.tick);
+        }
+    }
+    pub fn tick(&self) -> u32 {
+        self.tick
+    }
+    pub fn create_entity(&mut self) -> EntityID {
+        self.entities.allocate()
+    }
+    pub fn spawn(&mut self) -> EntityBuilder<'_> {
+        EntityBuilder::new(self)
+    }
+    pub fn despawn(&mut self, entity_id: EntityID) -> bool {
+        if !self.entities.free(entity_id) {
+            return false;
+        }
+        for archetype in &mut self.archetypes {
+            let mut columns = table_columns(&mut self.storages, archetype.types());
+            archetype.leave(&mut columns, entity_id);
+        }
+        for storage in self.storages.values_mut() {
+            storage.get_mut().remove_entity(entity_id);
+        }
+        true
+    }
+    // Forgets removed components, including those of despawned entities.
+    // Called at the end of every step, before its commands are applied, so
+    // every system of the next step sees what they removed. Systems have to
+    // remove and despawn through `Commands` for this: a removal made straight
+    // on a container is forgotten before the systems that ran earlier in the
+    // step see it.
+    pub fn clear_trackers(&mut self) {
+        for storage in self.storages.values_mut() {
+            storage.get_mut().clear_removed();
+        }
+    }
+    // Sync point for the `Commands` resource: runs everything queued so far.
+    pub fn apply_commands(&mut self) {
+        if !self.contains_resource::<Commands>() {
+            return;
+        }
+        let mut commands = std::mem::take(&mut *self.resource_mut::<Commands>());
+        commands.apply(self);
+    }
+    // Saves the entity allocator and every component and resource type in
+    // `registry`. Entity ids are kept as they are, so components that refer to
+    // other entities still do after `deserialize`.
+    pub fn serialize(&self, registry: &Registry) -> Result<WorldData, RegistryError> {
+        Ok(WorldData {
+            tick: self.tick,
+            entities: self.entities.clone(),
+            components: registry.save_components(self)?,
+            resources: registry.save_resources(self)?,
+        })
+    }
+    // Replaces the contents of the world with `data`. Every storage is
+    // emptied first, whether or not `registry` knows its type.
+    // If a value fails to deserialize the world is left partly loaded.
+    pub fn deserialize(
+        &mut self,
+        registry: &Registry,
+        data: &WorldData,
+    ) -> Result<(), RegistryError> {
+        registry.check(data)?;
+        for storage in self.storages.values_mut() {
+            storage.get_mut().clear();
+        }
+        self.clear_trackers();
+        self.tick = data.tick;
+        self.entities = data.entities.clone();
+        for storage in self.storages.values_mut() {
+            storage.get_mut().set_tick(self.tick);
+        }
+        registry.load_components(self, &data.components)?;
+        self.rebuild_archetypes();
+        registry.load_resources(self, &data.resources)?;
+        Ok(())
+    }
+    // Copies the world for rollback. Only types in `registry` are copied; the
+    // rest come back empty from `restore`.
+    pub fn snapshot(&self, registry: &Registry) -> Snapshot {
+        Snapshot {
+            tick: self.tick,
+            entities: self.entities.clone(),
+            data: registry.snapshot(self),
+        }
+    }
+    pub fn restore(&mut self, registry: &Registry, snapshot: &Snapshot) {
+        for storage in self.storages.values_mut() {
+            storage.get_mut().clear();
+        }
+        self.clear_trackers();
+        self.tick = snapshot.tick;
+        self.entities = snapshot.entities.clone();
+        registry.restore(self, &snapshot.data);
+        self.rebuild_archetypes();
+    }
+    pub fn is_alive(&self, entity_id: EntityID) -> bool {
+        self.entities.is_alive(entity_id)
+    }
+    pub fn is_registered<T: 'static>(&self) -> bool {
+        self.storages.contains_key(&TypeId::of::<T>())
+    }
+    pub fn register<T: Send + Sync + 'static>(&mut self) {
+        self.register_storage(CContainer::<T>::default());
+    }
+    pub fn register_sparse<T: Send + Sync + 'static>(&mut self) {
+        self.register_storage(CContainer::<T>::sparse());
+    }
+    fn register_storage<T: Send + Sync + 'static>(&mut self, mut storage: CContainer<T>) {
+        storage.set_tick(self.tick);
+        self.storages
+            .entry(TypeId::of::<T>())
+            .or_insert_with(|| SyncCell::new(Box::new(storage)));
+    }
+    // Stores the component types in `R` as one table, see `Archetype`. They
+    // have to be registered already and cannot be part of another archetype.
+    pub fn register_archetype<R: ArchetypeRow>(&mut self) {
+        let archetype = Archetype::new::<R>(self.archetypes.len() as u32);
+        for type_id in archetype.types() {
+            assert!(
+                self.archetype_of(*type_id).is_none(),
+                "component is already part of an archetype"
+            );
+            match self.storages.get_mut(type_id) {
+                Some(storage) => storage
+                    .get_mut()
+                    .as_column_mut()
+                    .set_table(Some(archetype.id())),
+                None => panic!("archetype components have to be registered first"),
+            }
+        }
+        self.archetypes.push(archetype);
+        self.rebuild_archetypes();
+    }
+    fn archetype_of(&self, type_id: TypeId) -> Option<usize> {
+        self.archetypes
+            .iter()
+            .position(|archetype| archetype.types().contains(&type_id))
+    }
+    fn rebuild_archetypes(&mut self) {
+        for archetype in &mut self.archetypes {
+            let mut columns = table_columns(&mut self.storages, archetype.types());
+            archetype.rebuild(&mut columns);
+        }
+    }
+    // Includes the components of type `T` in `state_hash`, after the types
+    // registered before it.
+    pub fn register_state_hash<T: StateHash + 'static>(&mut self) {
+        let type_id = TypeId::of::<T>();
+        if self.state_hashers.iter().all(|(id, _)| *id != type_id) {
+            self.state_hashers.push((type_id, hash_storage::<T>));
+        }
+    }
+    // Includes the resource `R` in `state_hash`, after the types registered
+    // before it.
+    pub fn register_resource_state_hash<R: StateHash + 'static>(&mut self) {
+        let type_id = TypeId::of::<R>();
+        if self.state_hashers.iter().all(|(id, _)| *id != type_id) {
+            self.state_hashers.push((type_id, hash_resource::<R>));
+        }
+    }
+    // A hash of the tick, the entity allocator and every component and
+    // resource registered with `register_state_hash` and
+    // `register_resource_state_hash`. Two runs that hash the same at every
+    // tick went through the same states, on whatever machine they ran.
+    pub fn state_hash(&self) -> u64 {
+        let mut hasher = StateHasher::default();
+        hasher.write_u32(self.tick);
+        self.entities.hash_state(&mut hasher);
+        for (_, hash) in &self.state_hashers {
+            hash(self, &mut hasher);
+        }
+        hasher.finish()
+    }
+    // Replaces the whole storage of `T`, keeping its index and trackers. If
+    // `T` is part of an archetype, the rows are only lined up again by
+    // `restore`, once every column is back.
+    pub fn set_storage<T: Send + Sync + 'static>(&mut self, mut storage: CContainer<T>) {
+        match self.storages.get_mut(&TypeId::of::<T>()) {
+            Some(cell) => {
+                let current = cell
+                    .get_mut()
+                    .as_any_mut()
+                    .downcast_mut::<CContainer<T>>()
+                    .unwrap();
+                storage.set_table(current.table());
+                *current = storage;
+            }
+            None => {
+                self.storages
+                    .insert(TypeId::of::<T>(), SyncCell::new(Box::new(storage)));
+            }
+        }
+    }
+    pub fn insert<T: Send + Sync + 'static>(&mut self, entity_id: EntityID, value: T) {
+        self.register::<T>();
+        self.storage_mut::<T>().push(entity_id, value);
+        if let Some(index) = self.archetype_of(TypeId::of::<T>()) {
+            let archetype = &mut self.archetypes[index];
+            let mut columns = table_columns(&mut self.storages, archetype.types());
+            archetype.join(&mut columns, entity_id);
+        }
+    }
+    pub fn remove<T: 'static>(&mut self, entity_id: EntityID) -> Option<T> {
+        if let Some(index) = self.archetype_of(TypeId::of::<T>()) {
+            let archetype = &mut self.archetypes[index];
+            let mut columns = table_columns(&mut self.storages, archetype.types());
+            archetype.leave(&mut columns, entity_id);
+        }
+        self.storage_mut::<T>().remove(entity_id)
+    }
+    pub fn get<T: 'static>(&self, entity_id: EntityID) -> Option<CellRef<'_, T>> {
+        CellRef::filter_map(self.storage::<T>(), |storage| storage.get(entity_id))
+    }
+    pub fn get_mut<T: 'static>(&self, entity_id: EntityID) -> Option<CellRefMut<'_, T>> {
+        CellRefMut::filter_map(self.storage_mut::<T>(), |storage| {
+            storage.get_mut(entity_id)
+        })
+    }
+    pub fn storage<T: 'static>(&self) -> CellRef<'_, CContainer<T>> {
+        CellRef::map(self.cell::<T>().borrow(), |storage| {
+            storage.as_any().downcast_ref::<CContainer<T>>().unwrap()
+        })
+    }
+    pub fn storage_mut<T: 'static>(&self) -> CellRefMut<'_, CContainer<T>> {
+        CellRefMut::map(self.cell::<T>().borrow_mut(), |storage| {
+            storage
+                .as_any_mut()
+                .downcast_mut::<CContainer<T>>()
+                .unwrap()
+        })
+    }
+    // Resources are singletons keyed by type, such as input state or the
+    // event channels. Inserting replaces the previous value.
+    pub fn insert_resource<R: Send + Sync + 'static>(&mut self, resource: R) {
+        self.resources
+            .insert(TypeId::of::<R>(), SyncCell::new(Box::new(resource)));
+    }
+    pub fn remove_resource<R: 'static>(&mut self) -> Option<R> {
+        let resource = self.resources.remove(&TypeId::of::<R>())?;
+        Some(*resource.into_inner().downcast::<R>().unwrap())
+    }
+    pub fn contains_resource<R: 'static>(&self) -> bool {
+        self.resources.contains_key(&TypeId::of::<R>())
+    }
+    pub fn resource<R: 'static>(&self) -> CellRef<'_, R> {
+        CellRef::map(self.resource_cell::<R>().borrow(), |resource| {
+            resource.downcast_ref::<R>().unwrap()
+        })
+    }
+    pub fn resource_mut<R: 'static>(&self) -> CellRefMut<'_, R> {
+        CellRefMut::map(self.resource_cell::<R>().borrow_mut(), |resource| {
+            resource.downcast_mut::<R>().unwrap()
+        })
+    }
+    fn resource_cell<R: 'static>(&self) -> &SyncCell<Box<dyn Any + Send + Sync>> {
+        match self.resources.get(&TypeId::of::<R>()) {
+            Some(cell) => cell,
+            None => panic!("resource {} is not inserted", std::any::type_name::<R>()),
+        }
+    }
+    fn cell<T: 'static>(&self) -> &SyncCell<Box<dyn AnyStorage>> {
+        match self.storages.get(&TypeId::of::<T>()) {
+            Some(cell) => cell,
+            None => panic!("component {} is not registered", std::any::type_name::<T>()),
+        }
+    }
+}
+
+// The containers of the archetype made of `types`, in that order.
+fn table_columns<'a>(
+    storages: &'a mut HashMap<TypeId, SyncCell<Box<dyn AnyStorage>>>,
+    types: &[TypeId],
+) -> Vec<&'a mut dyn TableColumn> {
+    let mut columns: Vec<Option<&mut dyn TableColumn>> = types.iter().map(|_| None).collect();
+    for (type_id, storage) in storages.iter_mut() {
+        if let Some(column) = types.iter().position(|column| column == type_id) {
+            columns[column] = Some(storage.get_mut().as_column_mut());
+        }
+    }
+    columns.into_iter().map(Option::unwrap).collect()
+}
+
+type HashStorage = fn(&World, &mut StateHasher);
+
+fn hash_storage<T: StateHash + 'static>(world: &World, hasher: &mut StateHasher) {
+    if !world.is_registered::<T>() {
+        hasher.write_usize(0);
+        return;
+    }
+    let storage = world.storage::<T>();
+    hasher.write_usize(storage.len());
+    for (entity_id, component) in storage.iter() {
+        entity_id.hash(hasher);
+        component.hash_state(hasher);
+    }
+}
+
+fn hash_resource<R: StateHash + 'static>(world: &World, hasher: &mut StateHasher) {
+    if !world.contains_resource::<R>() {
+        hasher.write_u8(0);
+        return;
+    }
+    hasher.write_u8(1);
+    world.resource::<R>().hash_state(hasher);
+}
+
+// The saved form of a world. Maps are keyed by the names in the `Registry`
+// and components keep their storage order, so saving the same world twice
+// gives the same output.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct WorldData {
+    pub tick: u32,
+    pub entities: EntityAllocator,
+    pub components: BTreeMap<String, ComponentRows>,
+    pub resources: BTreeMap<String, Value>,
+}
+
+// An in-memory copy of the world, cheap enough to take every step for
+// rollback. Unlike `WorldData` it is not meant to be stored.
+pub struct Snapshot {
+    tick: u32,
+    entities: EntityAllocator,
+    data: SnapshotData,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::query::*;
+    use crate::schedule::*;
+    use crate::systems::*;
+
+    struct Hashed(u32);
+    struct Sparse(u32);
+
+    // A despawned id keeps failing lookups after its index is handed out
+    // again, whichever index its components are stored under.
+    #[test]
+    fn stale_id_misses_after_reuse() {
+        let mut world = World::default();
+        world.register::<Hashed>();
+        world.register_sparse::<Sparse>();
+        let stale = world.create_entity();
+        world.insert(stale, Hashed(1));
+        world.insert(stale, Sparse(1));
+        assert!(world.despawn(stale));
+
+        let entity_id = world.create_entity();
+        assert_eq!(entity_id.index(), stale.index());
+        assert_ne!(entity_id, stale);
+        world.insert(entity_id, Hashed(2));
+        world.insert(entity_id, Sparse(2));
+
+        assert!(!world.is_alive(stale));
+        assert!(world.get::<Hashed>(stale).is_none());
+        assert!(world.get::<Sparse>(stale).is_none());
+        assert!(!world.storage::<Hashed>().contains(stale));
+        assert!(!world.storage::<Sparse>().contains(stale));
+        assert!(!world.despawn(stale));
+
+        assert!(world.is_alive(entity_id));
+        assert_eq!(world.get::<Hashed>(entity_id).unwrap().0, 2);
+        assert_eq!(world.get::<Sparse>(entity_id).unwrap().0, 2);
+    }
+
+    struct A(u32);
+    struct B(u32);
+
+    // Entities with both components, read off the shared rows.
+    fn shared_rows(world: &World) -> Vec<EntityID> {
+        let a = world.storage::<A>();
+        let b = world.storage::<B>();
+        (0..a.len().min(b.len()))
+            .take_while(|row| a.entity_at(*row) == b.entity_at(*row))
+            .map(|row| a.entity_at(row))
+            .collect()
+    }
+
+    #[test]
+    fn rows_stay_in_line() {
+        let mut world = World::default();
+        world.register::<A>();
+        world.register_sparse::<B>();
+        world.register_archetype::<(A, B)>();
+        let only_a = world.create_entity();
+        world.insert(only_a, A(0));
+        let first = world.create_entity();
+        world.insert(first, B(1));
+        world.insert(first, A(1));
+        let second = world.create_entity();
+        world.insert(second, A(2));
+        world.insert(second, B(2));
+        assert_eq!(shared_rows(&world), vec![first, second]);
+
+        world.remove::<B>(first);
+        assert_eq!(shared_rows(&world), vec![second]);
+        world.insert(only_a, B(0));
+        assert_eq!(shared_rows(&world), vec![second, only_a]);
+        world.despawn(second);
+        assert_eq!(shared_rows(&world), vec![only_a]);
+        assert_eq!(world.get::<A>(first).unwrap().0, 1);
+        assert_eq!(world.get::<B>(only_a).unwrap().0, 0);
+    }
+
+    #[test]
+    fn queries_join_the_group() {
+        let mut world = World::default();
+        world.register::<A>();
+        world.register::<B>();
+        let mut entities = Vec::new();
+        for i in 0..6 {
+            let entity_id = world.create_entity();
+            world.insert(entity_id, A(i));
+            entities.push(entity_id);
+        }
+        for (i, entity_id) in entities.iter().enumerate().rev() {
+            if i % 3 != 0 {
+                world.insert(*entity_id, B(i as u32 * 10));
+            }
+        }
+        // Grouping after the fact lines up what is already there.
+        world.register_archetype::<(A, B)>();
+        assert_eq!(shared_rows(&world).len(), 4);
+
+        let mut a = world.storage_mut::<A>();
+        let b = world.storage::<B>();
+        let mut joined: Vec<_> = (&mut *a, &*b)
+            .query()
+            .map(|(entity_id, a, b)| {
+                a.0 += 1;
+                (entity_id, a.0, b.0)
+            })
+            .collect();
+        joined.sort_by_key(|(_, a, _)| *a);
+        let expected: Vec<_> = [1, 2, 4, 5]
+            .iter()
+            .map(|i| (entities[*i], *i as u32 + 1, *i as u32 * 10))
+            .collect();
+        assert_eq!(joined, expected);
+    }
+
+    // What `RemovedComponents<A>` held when the system last ran.
+    #[derive(Default)]
+    struct SeenRemoved(Vec<EntityID>);
+
+    impl Resource for SeenRemoved {}
+
+    impl SystemProcess for System<SeenRemoved, (RemovedComponents<'_, A>,)> {
+        fn process(seen: &mut Self::Update, (removed,): &Self::Refer) {
+            seen.0 = removed.to_vec();
+        }
+    }
+
+    // The same order as `Simulation::step`.
+    fn step(world: &mut World, schedule: &Schedule) {
+        world.advance_tick();
+        schedule.run(world);
+        world.clear_trackers();
+        world.apply_commands();
+    }
+
+    #[test]
+    fn removals_are_seen_for_one_step() {
+        let mut world = World::default();
+        world.register::<A>();
+        world.insert_resource(Commands::default());
+        world.insert_resource(SeenRemoved::default());
+        let mut schedule = Schedule::default();
+        schedule.add(system::<SeenRemoved, (RemovedComponents<A>,)>(
+            "seen_removed",
+        ));
+        schedule.build().unwrap();
+        let removed = world.create_entity();
+        let despawned = world.create_entity();
+        let kept = world.create_entity();
+        for entity_id in &[removed, despawned, kept] {
+            world.insert(*entity_id, A(0));
+        }
+
+        world.resource_mut::<Commands>().remove::<A>(removed);
+        world.resource_mut::<Commands>().add(move |world| {
+            world.despawn(despawned);
+        });
+        step(&mut world, &schedule);
+        assert!(world.resource::<SeenRemoved>().0.is_empty());
+        step(&mut world, &schedule);
+        assert_eq!(world.resource::<SeenRemoved>().0, vec![removed, despawned]);
+        step(&mut world, &schedule);
+        assert!(world.resource::<SeenRemoved>().0.is_empty());
+    }
+}
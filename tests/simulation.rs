@@ -0,0 +1,101 @@
+use rust_ecs_game::{Input, Simulation};
+use serde_json::Value;
+
+// Position of the first entity spawned from `prefab`, read from the saved
+// state.
+fn position(state: &Value, prefab: &str) -> (f64, f64) {
+    let components = &state["components"];
+    let names = components["PrefabName"].as_array().unwrap();
+    let (entity, _) = names
+        .iter()
+        .map(|row| (&row[0], &row[1]))
+        .find(|(_, name)| *name == prefab)
+        .unwrap();
+    let positions = components["Position"].as_array().unwrap();
+    let position = &positions.iter().find(|row| &row[0] == entity).unwrap()[1];
+    (
+        position["x"].as_f64().unwrap(),
+        position["y"].as_f64().unwrap(),
+    )
+}
+
+fn run(ticks: u32, script: impl FnMut(u32) -> Input) -> Value {
+    let mut simulation = Simulation::new().unwrap();
+    simulation.run(ticks, script);
+    assert_eq!(simulation.tick(), ticks);
+    simulation.state().unwrap()
+}
+
+// Holding right for one second walks the hero its walking speed further than
+// standing still does, and leaves everything else where it was.
+#[test]
+fn scripted_ticks_move_the_hero() {
+    let idle = run(120, |_| Input::default());
+    let walked = run(120, |tick| Input {
+        right: (30..90).contains(&tick),
+        ..Input::default()
+    });
+
+    let (idle_x, idle_y) = position(&idle, "hero");
+    let (walked_x, walked_y) = position(&walked, "hero");
+    assert!(
+        (walked_x - idle_x - 120.0).abs() < 0.01,
+        "{} {}",
+        walked_x,
+        idle_x
+    );
+    assert_eq!(walked_y, idle_y);
+    assert_eq!(position(&walked, "enemy"), position(&idle, "enemy"));
+}
+
+#[test]
+fn same_script_ends_in_the_same_state() {
+    let script = |tick: u32| Input {
+        down: tick < 40,
+        attack: tick.is_multiple_of(30),
+        ..Input::default()
+    };
+    assert_eq!(run(120, script), run(120, script));
+}
+
+// A recording of a fight replays into the state it was recorded in.
+#[test]
+fn recorded_ticks_replay() {
+    let mut simulation = Simulation::new().unwrap();
+    simulation.start_recording().unwrap();
+    simulation.run(120, |tick| Input {
+        left: tick < 40,
+        up: tick < 40,
+        attack: tick % 20 == 10,
+        ..Input::default()
+    });
+    let recording = simulation.stop_recording().unwrap();
+    assert_eq!(recording.ticks(), 120);
+
+    let hits = &simulation.state().unwrap()["resources"]["AnimatorHitReader"];
+    assert!(hits["last_event_count"].as_u64().unwrap() > 0);
+
+    let mut replayed = Simulation::new().unwrap();
+    assert!(replayed.replay(recording).is_ok());
+    assert_eq!(replayed.state_hash(), simulation.state_hash());
+}
+
+// Restoring a snapshot undoes every step taken since it was made.
+#[test]
+fn restore_rewinds_to_the_snapshot() {
+    let mut simulation = Simulation::new().unwrap();
+    simulation.run(30, |_| Input::default());
+    let snapshot = simulation.snapshot();
+    let (tick, state_hash) = (simulation.tick(), simulation.state_hash());
+
+    simulation.run(60, |tick| Input {
+        right: tick < 20,
+        attack: tick == 30,
+        ..Input::default()
+    });
+    assert_ne!(simulation.state_hash(), state_hash);
+
+    simulation.restore(&snapshot);
+    assert_eq!(simulation.tick(), tick);
+    assert_eq!(simulation.state_hash(), state_hash);
+}